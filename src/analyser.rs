@@ -1,4 +1,8 @@
-use std::path::PathBuf;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use url::Url;
 
 use crate::error::Error;
 
@@ -8,6 +12,111 @@ pub enum TempItem
     File(PathBuf),
     Url(String),
     Text(String),
+    /// An HTML document.
+    Html(String),
+    /// A JSON document.
+    Json(String),
+}
+
+/// Extra information about a resource.
+#[derive(PartialEq, Debug, Default, Clone)]
+pub struct ResourceInfo
+{
+    /// What the resource is in the record, e.g. “video” or
+    /// “subtitles”.
+    pub role: Option<String>,
+    /// How the content of the resource relates to the content at the
+    /// URL of the record, e.g. “quoted” for a quoted tweet.
+    pub relation: Option<String>,
+    /// The original URL of a resource that is not downloaded from
+    /// there, e.g. a file copied from a local export.
+    pub url: Option<String>,
+    /// A textual description of an image or video, e.g. its alt
+    /// text.
+    pub description: Option<String>,
+    /// The MIME type of the resource, if known from the source.
+    pub content_type: Option<String>,
+    /// The length of an audio or video in milliseconds.
+    pub duration_ms: Option<u64>,
+    /// The aspect ratio of an image or video, like “16:9”.
+    pub aspect_ratio: Option<String>,
+    /// The original filename of the resource, e.g. from the
+    /// Content-Disposition header. The resource is still stored under
+    /// a hashed name.
+    pub original_name: Option<String>,
+}
+
+#[derive(PartialEq, Debug)]
+pub struct Resource
+{
+    pub item: TempItem,
+    pub info: ResourceInfo,
+}
+
+impl From<TempItem> for Resource
+{
+    fn from(item: TempItem) -> Self
+    {
+        Self { item, info: ResourceInfo::default() }
+    }
+}
+
+/// Information about the original content, as far as the analyser can
+/// tell.
+#[derive(PartialEq, Debug, Default, Clone)]
+pub struct RecordInfo
+{
+    pub title: Option<String>,
+    pub authors: Vec<String>,
+    /// When the content was published, in whatever format the source
+    /// provides.
+    pub published: Option<String>,
+}
+
+/// The result of a ResourceAnalyser.
+#[derive(PartialEq, Debug, Default)]
+pub struct Analysis
+{
+    pub resources: Vec<Resource>,
+    pub info: RecordInfo,
+}
+
+impl Analysis
+{
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    pub fn addWithRole(&mut self, item: TempItem, role: &str)
+    {
+        self.addWithInfo(item, ResourceInfo {
+            role: Some(role.to_owned()),
+            ..ResourceInfo::default()
+        });
+    }
+
+    pub fn addWithInfo(&mut self, item: TempItem, info: ResourceInfo)
+    {
+        self.resources.push(Resource { item, info });
+    }
+
+    #[cfg(test)]
+    pub fn items(&self) -> Vec<&TempItem>
+    {
+        self.resources.iter().map(|r| &r.item).collect()
+    }
+}
+
+impl From<Vec<TempItem>> for Analysis
+{
+    fn from(items: Vec<TempItem>) -> Self
+    {
+        Self {
+            resources: items.into_iter().map(Resource::from).collect(),
+            info: RecordInfo::default(),
+        }
+    }
 }
 
 /// A ResourceAnalyser figures out the required resources from the
@@ -17,5 +126,119 @@ pub enum TempItem
 /// categories and metadata.
 pub trait ResourceAnalyser
 {
-    fn analyse(&self, url: &str) -> Result<Vec<TempItem>, Error>;
+    /// Analyse `url`. Temporary files can be created in the directory
+    /// `scratch`, which lives until the record is created.
+    fn analyse(&self, url: &str, scratch: &Path) -> Result<Analysis, Error>;
+}
+
+/// Match `s` against a glob `pattern`, where “*” matches any sequence
+/// of characters and “?” matches exactly one character. The match is
+/// case-insensitive.
+pub fn globMatch(pattern: &str, s: &str) -> bool
+{
+    let p: Vec<char> = pattern.to_lowercase().chars().collect();
+    let s: Vec<char> = s.to_lowercase().chars().collect();
+    let mut pi = 0;
+    let mut si = 0;
+    // Position of the last “*” in the pattern, and the position in
+    // `s` it is currently matched up to.
+    let mut star: Option<(usize, usize)> = None;
+    while si < s.len()
+    {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == s[si])
+        {
+            pi += 1;
+            si += 1;
+        }
+        else if pi < p.len() && p[pi] == '*'
+        {
+            star = Some((pi, si));
+            pi += 1;
+        }
+        else if let Some((star_p, star_s)) = star
+        {
+            // Let the last star eat one more character and retry.
+            pi = star_p + 1;
+            si = star_s + 1;
+            star = Some((star_p, star_s + 1));
+        }
+        else
+        {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+/// Describes a set of URLs an analyser is able to handle. The host of
+/// the URL should match the glob `host`, and if `path` is not `None`,
+/// the path of the URL should match the regular expression.
+#[derive(Clone)]
+pub struct UrlRule
+{
+    pub host: String,
+    pub path: Option<Regex>,
+}
+
+impl UrlRule
+{
+    pub fn new(host: &str) -> Self
+    {
+        Self { host: host.to_owned(), path: None }
+    }
+
+    pub fn withPath(host: &str, path: &str) -> Result<Self, Error>
+    {
+        let re = Regex::new(path).map_err(
+            |e| rterr!("Invalid path regex “{}”: {}", path, e))?;
+        Ok(Self { host: host.to_owned(), path: Some(re) })
+    }
+
+    pub fn matches(&self, url: &Url) -> bool
+    {
+        let host = match url.host_str()
+        {
+            Some(h) => h,
+            None => return false,
+        };
+        if !globMatch(&self.host, host)
+        {
+            return false;
+        }
+        match &self.path
+        {
+            Some(re) => re.is_match(url.path()),
+            None => true,
+        }
+    }
+}
+
+impl fmt::Display for UrlRule
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match &self.path
+        {
+            Some(re) => write!(f, "{} {}", self.host, re.as_str()),
+            None => write!(f, "{}", self.host),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn glob()
+    {
+        assert!(globMatch("*", "example.org"));
+        assert!(globMatch("*.example.org", "www.example.org"));
+        assert!(!globMatch("*.example.org", "example.org"));
+        assert!(globMatch("nitter.*", "nitter.net"));
+        assert!(globMatch("a*b*c", "aXXbYYbc"));
+        assert!(globMatch("ex?mple.org", "EXAMPLE.org"));
+        assert!(!globMatch("example.org", "example.org.evil"));
+    }
 }
@@ -0,0 +1,736 @@
+//! Extracting the main article of a web page, without the navigation,
+//! ads and so on. This is a simplified version of the algorithm of
+//! Mozilla’s Readability: paragraphs give scores to their ancestors,
+//! and the ancestor with the highest score is taken as the article.
+
+use regex::Regex;
+use scraper::{ElementRef, Html, Node, Selector};
+use serde_json::Value;
+
+use crate::html;
+
+/// An article is not worth extracting if it has less text than this.
+const MIN_ARTICLE_LENGTH: usize = 250;
+
+/// Elements that are never part of an article.
+static UNWANTED_TAGS: &[&str] = &[
+    "script", "style", "noscript", "iframe", "form", "input", "button",
+    "select", "textarea", "nav", "aside", "footer", "svg", "object",
+    "embed", "link", "meta", "template", "dialog", "canvas"];
+
+/// Elements that are kept as they are in the extracted article.
+static KEPT_TAGS: &[&str] = &[
+    "p", "h1", "h2", "h3", "h4", "h5", "h6", "a", "img", "figure",
+    "figcaption", "blockquote", "pre", "code", "em", "strong", "b", "i",
+    "u", "s", "sub", "sup", "ul", "ol", "li", "dl", "dt", "dd", "table",
+    "thead", "tbody", "tfoot", "tr", "th", "td", "caption", "br", "hr",
+    "div", "section"];
+
+/// Attributes that are kept in the extracted article.
+static KEPT_ATTRIBUTES: &[&str] = &["href", "src", "alt", "title", "colspan", "rowspan"];
+
+static VOID_TAGS: &[&str] = &["br", "hr", "img"];
+
+#[derive(Debug, PartialEq)]
+pub struct Article
+{
+    pub title: Option<String>,
+    pub authors: Vec<String>,
+    pub published: Option<String>,
+    /// The HTML of the article content.
+    pub content: String,
+}
+
+/// Patterns of the class names and IDs of elements, which hint
+/// whether they are part of the article.
+struct Hints
+{
+    unlikely: Regex,
+    maybe: Regex,
+    positive: Regex,
+    negative: Regex,
+}
+
+impl Hints
+{
+    fn new() -> Self
+    {
+        Self {
+            unlikely: Regex::new(
+                r"(?i)-ad-|ai2html|banner|breadcrumbs|combx|comment|community|cookie|disqus|extra|footer|gdpr|header|legends|menu|newsletter|pager|pagination|popup|promo|related|remark|replies|rss|share|shoutbox|sidebar|skyscraper|social|sponsor|subscribe|supplemental")
+                .unwrap(),
+            maybe: Regex::new(r"(?i)and|article|body|column|content|main|shadow").unwrap(),
+            positive: Regex::new(
+                r"(?i)article|body|content|entry|hentry|h-entry|main|page|post|text|blog|story")
+                .unwrap(),
+            negative: Regex::new(
+                r"(?i)-ad-|hidden|banner|combx|comment|com-|contact|foot|footnote|gdpr|masthead|media|meta|outbrain|promo|related|scroll|share|shoutbox|sidebar|skyscraper|sponsor|shopping|tags|tool|widget")
+                .unwrap(),
+        }
+    }
+
+    fn classAndId(el: &ElementRef) -> String
+    {
+        format!("{} {}", el.attr("class").unwrap_or(""), el.attr("id").unwrap_or(""))
+    }
+
+    /// Whether `el` is unlikely to be part of the article.
+    fn isUnlikely(&self, el: &ElementRef) -> bool
+    {
+        let name = el.value().name();
+        if name == "body" || name == "article" || name == "main"
+        {
+            return false;
+        }
+        let hint = Self::classAndId(el);
+        self.unlikely.is_match(&hint) && !self.maybe.is_match(&hint) &&
+            !self.positive.is_match(&hint)
+    }
+
+    fn weight(&self, el: &ElementRef) -> f64
+    {
+        let hint = Self::classAndId(el);
+        let mut weight = 0.0;
+        if self.positive.is_match(&hint)
+        {
+            weight += 25.0;
+        }
+        if self.negative.is_match(&hint)
+        {
+            weight -= 25.0;
+        }
+        weight
+    }
+}
+
+fn selector(s: &str) -> Selector
+{
+    Selector::parse(s).unwrap()
+}
+
+fn normalizeSpace(s: &str) -> String
+{
+    s.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+fn textOf(el: &ElementRef) -> String
+{
+    normalizeSpace(&el.text().collect::<String>())
+}
+
+/// The proportion of the text of `el` that is in links.
+fn linkDensity(el: &ElementRef) -> f64
+{
+    let length = textOf(el).len();
+    if length == 0
+    {
+        return 0.0;
+    }
+    let link_length: usize = el.select(&selector("a")).map(|a| textOf(&a).len()).sum();
+    link_length as f64 / length as f64
+}
+
+fn tagWeight(name: &str) -> f64
+{
+    match name
+    {
+        "div" | "article" | "main" => 5.0,
+        "pre" | "td" | "blockquote" => 3.0,
+        "address" | "ol" | "ul" | "dl" | "dd" | "dt" | "li" | "form" => -3.0,
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "th" => -5.0,
+        _ => 0.0,
+    }
+}
+
+/// Whether `el` or any of its ancestors is not part of an article.
+fn isExcluded(el: &ElementRef, hints: &Hints) -> bool
+{
+    let mut node = Some(**el);
+    while let Some(n) = node
+    {
+        if let Some(e) = ElementRef::wrap(n)
+        {
+            if UNWANTED_TAGS.contains(&e.value().name()) || hints.isUnlikely(&e)
+            {
+                return true;
+            }
+        }
+        node = n.parent();
+    }
+    false
+}
+
+/// Find the element that contains the article in the body of `doc`.
+fn topCandidate<'a>(doc: &'a Html, hints: &Hints) -> Option<ElementRef<'a>>
+{
+    let mut scores: Vec<(ElementRef<'a>, f64)> = Vec::new();
+    let paragraphs = selector("p, pre, td, blockquote");
+    for p in doc.select(&paragraphs)
+    {
+        if isExcluded(&p, hints)
+        {
+            continue;
+        }
+        let text = textOf(&p);
+        if text.len() < 25
+        {
+            continue;
+        }
+        let score = 1.0 + text.matches(',').count() as f64 +
+            (text.len() as f64 / 100.0).min(3.0);
+        for (level, ancestor) in p.ancestors().filter_map(ElementRef::wrap).take(3)
+            .enumerate()
+        {
+            let share = match level
+            {
+                0 => score,
+                1 => score / 2.0,
+                _ => score / (level as f64 * 3.0),
+            };
+            match scores.iter_mut().find(|(e, _)| *e == ancestor)
+            {
+                Some((_, s)) => *s += share,
+                None => scores.push(
+                    (ancestor, tagWeight(ancestor.value().name()) +
+                     hints.weight(&ancestor) + share)),
+            }
+        }
+    }
+    scores.into_iter()
+        .map(|(e, s)| (e, s * (1.0 - linkDensity(&e))))
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(e, _)| e)
+}
+
+/// Serialize `el` with only the tags and attributes that make sense
+/// in an article.
+fn clean(el: &ElementRef, hints: &Hints, title: Option<&str>, out: &mut String)
+{
+    let name = el.value().name();
+    if UNWANTED_TAGS.contains(&name) || hints.isUnlikely(el)
+    {
+        return;
+    }
+    if matches!(name, "div" | "section" | "ul" | "ol" | "table") && linkDensity(el) > 0.5
+    {
+        return;
+    }
+    // The title is shown separately.
+    if name == "h1" && title.is_some() && Some(textOf(el).as_str()) == title
+    {
+        return;
+    }
+    let kept = KEPT_TAGS.contains(&name);
+    if kept
+    {
+        out.push('<');
+        out.push_str(name);
+        for attr in KEPT_ATTRIBUTES
+        {
+            if let Some(value) = el.value().attr(attr)
+            {
+                out.push_str(&format!(" {}=\"{}\"", attr, html::escape(value)));
+            }
+        }
+        out.push('>');
+        if VOID_TAGS.contains(&name)
+        {
+            return;
+        }
+    }
+    for child in el.children()
+    {
+        match child.value()
+        {
+            Node::Text(t) => out.push_str(&html::escape(t)),
+            Node::Element(_) => clean(&ElementRef::wrap(child).unwrap(), hints, title, out),
+            _ => {},
+        }
+    }
+    if kept
+    {
+        out.push_str(&format!("</{}>", name));
+    }
+}
+
+/// Collect the authors in the `author` field of JSON-LD.
+fn jsonLdAuthors(author: &Value, authors: &mut Vec<String>)
+{
+    match author
+    {
+        Value::String(s) => authors.push(s.clone()),
+        Value::Object(_) => if let Some(name) = author["name"].as_str()
+        {
+            authors.push(name.to_owned());
+        },
+        Value::Array(list) => for a in list
+        {
+            jsonLdAuthors(a, authors);
+        },
+        _ => {},
+    }
+}
+
+/// Get the authors and publish date from the JSON-LD in `doc`.
+fn jsonLd(doc: &Html) -> (Vec<String>, Option<String>)
+{
+    let mut authors = Vec::new();
+    let mut published = None;
+    for script in doc.select(&selector(r#"script[type="application/ld+json"]"#))
+    {
+        let data: Value = match serde_json::from_str(&script.text().collect::<String>())
+        {
+            Ok(data) => data,
+            Err(_) => continue,
+        };
+        let mut items = match data
+        {
+            Value::Array(list) => list,
+            _ => vec![data],
+        };
+        let graph: Vec<Value> = items.iter().filter_map(|item| item["@graph"].as_array())
+            .flatten().cloned().collect();
+        items.extend(graph);
+        for item in items
+        {
+            if authors.is_empty()
+            {
+                jsonLdAuthors(&item["author"], &mut authors);
+            }
+            if published.is_none()
+            {
+                published = item["datePublished"].as_str().map(str::to_owned);
+            }
+        }
+    }
+    (authors, published)
+}
+
+/// Get the value of the first meta element matching any of
+/// `selectors`.
+fn metaContent(doc: &Html, selectors: &[&str]) -> Option<String>
+{
+    selectors.iter().filter_map(|s| {
+        doc.select(&selector(s)).filter_map(|e| e.attr("content"))
+            .map(normalizeSpace).find(|c| !c.is_empty())
+    }).next()
+}
+
+fn findTitle(doc: &Html) -> Option<String>
+{
+    metaContent(doc, &[r#"meta[property="og:title"]"#, r#"meta[name="twitter:title"]"#])
+        .or_else(|| doc.select(&selector("title")).map(|e| textOf(&e))
+                 .find(|t| !t.is_empty()))
+        .or_else(|| doc.select(&selector("h1")).map(|e| textOf(&e))
+                 .find(|t| !t.is_empty()))
+}
+
+fn findAuthors(doc: &Html, json_ld: Vec<String>) -> Vec<String>
+{
+    if !json_ld.is_empty()
+    {
+        return json_ld;
+    }
+    if let Some(author) = metaContent(
+        doc, &[r#"meta[name="author"]"#, r#"meta[property="article:author"]"#])
+        .filter(|a| !a.starts_with("http"))
+    {
+        return vec![author];
+    }
+    doc.select(&selector(r#"[rel="author"], [itemprop="author"], .byline, .author"#))
+        .map(|e| textOf(&e)).find(|t| !t.is_empty() && t.len() < 100)
+        .into_iter().collect()
+}
+
+fn findPublished(doc: &Html, json_ld: Option<String>) -> Option<String>
+{
+    metaContent(doc, &[r#"meta[property="article:published_time"]"#,
+                       r#"meta[itemprop="datePublished"]"#,
+                       r#"meta[name="date"]"#,
+                       r#"meta[name="DC.date.issued"]"#])
+        .or(json_ld)
+        .or_else(|| doc.select(&selector("time[datetime]"))
+                 .filter_map(|e| e.attr("datetime")).map(str::to_owned).next())
+}
+
+/// Extract the article in the HTML document `doc`. Return `None` if
+/// there does not seem to be one.
+pub fn extract(doc: &str) -> Option<Article>
+{
+    let doc = Html::parse_document(doc);
+    let hints = Hints::new();
+    let top = topCandidate(&doc, &hints)?;
+    let title = findTitle(&doc);
+
+    // Siblings of the top candidate can also be part of the article,
+    // e.g. when the paragraphs are not in one container.
+    let mut content = String::new();
+    let parts: Vec<ElementRef> = match top.parent().and_then(ElementRef::wrap)
+    {
+        Some(parent) if top.value().name() != "body" => parent.child_elements().filter(
+            |e| *e == top || (e.value().name() == "p" && linkDensity(e) < 0.25 &&
+                              textOf(e).len() > 80)).collect(),
+        _ => vec![top],
+    };
+    for part in parts
+    {
+        clean(&part, &hints, title.as_deref(), &mut content);
+    }
+    let content = content.trim().to_owned();
+    if textOf(&Html::parse_fragment(&content).root_element()).len() < MIN_ARTICLE_LENGTH
+    {
+        return None;
+    }
+
+    let (json_ld_authors, json_ld_published) = jsonLd(&doc);
+    Some(Article {
+        title,
+        authors: findAuthors(&doc, json_ld_authors),
+        published: findPublished(&doc, json_ld_published),
+        content,
+    })
+}
+
+/// The byline of `article`, e.g. “Alice, Bob · 2023-01-01”.
+fn byline(article: &Article) -> String
+{
+    let mut parts = Vec::new();
+    if !article.authors.is_empty()
+    {
+        parts.push(article.authors.join(", "));
+    }
+    if let Some(p) = &article.published
+    {
+        parts.push(p.clone());
+    }
+    parts.join(" · ")
+}
+
+/// Make a standalone HTML page of `article`.
+pub fn renderHtml(article: &Article) -> String
+{
+    let title = article.title.as_deref().unwrap_or("");
+    html::page(title, &format!("<h1>{}</h1>\n<div class=\"meta\">{}</div>\n{}",
+                               html::escape(title), html::escape(&byline(article)),
+                               article.content))
+}
+
+/// Remove the blank lines at the ends of `s`, and merge consecutive
+/// blank lines.
+fn tidy(s: &str) -> String
+{
+    let mut lines: Vec<&str> = Vec::new();
+    for line in s.lines().map(|l| l.trim_end())
+    {
+        if line.is_empty() && lines.last().is_none_or(|l| l.is_empty())
+        {
+            continue;
+        }
+        lines.push(line);
+    }
+    while lines.last() == Some(&"")
+    {
+        lines.pop();
+    }
+    lines.join("\n")
+}
+
+fn escapeMarkdown(s: &str) -> String
+{
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars()
+    {
+        if matches!(c, '\\' | '*' | '_' | '`' | '[' | ']')
+        {
+            result.push('\\');
+        }
+        result.push(c);
+    }
+    result
+}
+
+/// Converter of the HTML of an article to Markdown.
+struct Markdown
+{
+    out: String,
+}
+
+impl Markdown
+{
+    fn render(el: &ElementRef) -> String
+    {
+        let mut md = Self { out: String::new() };
+        md.children(el);
+        tidy(&md.out)
+    }
+
+    /// Add the text of a text node. Whitespace is collapsed, but
+    /// still separates the text from the inline elements around it.
+    fn text(&mut self, text: &str)
+    {
+        let words = normalizeSpace(text);
+        let after_space = self.out.is_empty() || self.out.ends_with(char::is_whitespace);
+        if text.starts_with(char::is_whitespace) && !after_space
+        {
+            self.out.push(' ');
+        }
+        self.out.push_str(&escapeMarkdown(&words));
+        if !words.is_empty() && text.ends_with(char::is_whitespace)
+        {
+            self.out.push(' ');
+        }
+    }
+
+    /// Start a new block.
+    fn block(&mut self)
+    {
+        if self.out.is_empty()
+        {
+            return;
+        }
+        let trimmed = self.out.trim_end_matches(' ').len();
+        self.out.truncate(trimmed);
+        while !self.out.ends_with("\n\n")
+        {
+            self.out.push('\n');
+        }
+    }
+
+    fn children(&mut self, el: &ElementRef)
+    {
+        for child in el.children()
+        {
+            match child.value()
+            {
+                Node::Text(t) => self.text(t),
+                Node::Element(_) => self.element(&ElementRef::wrap(child).unwrap()),
+                _ => {},
+            }
+        }
+    }
+
+    fn element(&mut self, el: &ElementRef)
+    {
+        let name = el.value().name();
+        match name
+        {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" =>
+            {
+                self.block();
+                let level = match name
+                {
+                    "h1" => 1,
+                    "h2" => 2,
+                    "h3" => 3,
+                    "h4" => 4,
+                    "h5" => 5,
+                    _ => 6,
+                };
+                self.out.push_str(&format!("{} ", "#".repeat(level)));
+                self.children(el);
+                self.block();
+            },
+            "p" | "div" | "section" | "figure" | "figcaption" | "dl" | "dt" | "dd" |
+            "caption" =>
+            {
+                self.block();
+                self.children(el);
+                self.block();
+            },
+            "blockquote" =>
+            {
+                self.block();
+                for line in Self::render(el).lines()
+                {
+                    self.out.push_str(if line.is_empty() {">"} else {"> "});
+                    self.out.push_str(line);
+                    self.out.push('\n');
+                }
+                self.block();
+            },
+            "ul" | "ol" =>
+            {
+                self.block();
+                for (i, item) in el.child_elements().filter(|e| e.value().name() == "li")
+                    .enumerate()
+                {
+                    let marker = if name == "ol" {format!("{}. ", i + 1)}
+                                 else {String::from("- ")};
+                    let indent = " ".repeat(marker.len());
+                    for (j, line) in Self::render(&item).lines().enumerate()
+                    {
+                        if j == 0
+                        {
+                            self.out.push_str(&marker);
+                        }
+                        else if !line.is_empty()
+                        {
+                            self.out.push_str(&indent);
+                        }
+                        self.out.push_str(line);
+                        self.out.push('\n');
+                    }
+                }
+                self.block();
+            },
+            "pre" =>
+            {
+                self.block();
+                let code: String = el.text().collect();
+                self.out.push_str(&format!("```\n{}\n```", code.trim_end_matches('\n')));
+                self.block();
+            },
+            "code" => self.out.push_str(&format!("`{}`", el.text().collect::<String>())),
+            "strong" | "b" =>
+            {
+                self.out.push_str("**");
+                self.children(el);
+                self.out.push_str("**");
+            },
+            "em" | "i" =>
+            {
+                self.out.push('*');
+                self.children(el);
+                self.out.push('*');
+            },
+            "a" => match el.attr("href")
+            {
+                Some(href) =>
+                {
+                    self.out.push('[');
+                    self.children(el);
+                    self.out.push_str(&format!("]({})", href));
+                },
+                None => self.children(el),
+            },
+            "img" => match el.attr("src")
+            {
+                // Images inlined in the snapshot are too large to be
+                // useful in Markdown.
+                Some(src) if !src.starts_with("data:") =>
+                    self.out.push_str(&format!("![{}]({})",
+                                               escapeMarkdown(el.attr("alt").unwrap_or("")),
+                                               src)),
+                _ => {},
+            },
+            "br" => self.out.push_str("  \n"),
+            "hr" =>
+            {
+                self.block();
+                self.out.push_str("---");
+                self.block();
+            },
+            "table" =>
+            {
+                self.block();
+                for (i, row) in el.select(&selector("tr")).enumerate()
+                {
+                    let cells: Vec<String> = row.child_elements().map(
+                        |cell| Self::render(&cell).replace('\n', " ").replace('|', "\\|"))
+                        .collect();
+                    self.out.push_str(&format!("| {} |\n", cells.join(" | ")));
+                    if i == 0
+                    {
+                        self.out.push_str(&format!("|{}\n", " --- |".repeat(cells.len())));
+                    }
+                }
+                self.block();
+            },
+            _ => self.children(el),
+        }
+    }
+}
+
+/// Convert `article` to Markdown.
+pub fn renderMarkdown(article: &Article) -> String
+{
+    let content = Html::parse_fragment(&article.content);
+    let mut result = String::new();
+    if let Some(title) = &article.title
+    {
+        result.push_str(&format!("# {}\n\n", escapeMarkdown(title)));
+    }
+    let byline = byline(article);
+    if !byline.is_empty()
+    {
+        result.push_str(&format!("*{}*\n\n", escapeMarkdown(&byline)));
+    }
+    result.push_str(&Markdown::render(&content.root_element()));
+    result.push('\n');
+    result
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    static PAGE: &str = r##"<!DOCTYPE html>
+<html><head><title>A Story | Example News</title>
+<meta property="og:title" content="A Story">
+<meta property="article:published_time" content="2023-04-05T06:07:08Z">
+<script type="application/ld+json">
+{"@context": "https://schema.org", "@graph": [{"@type": "NewsArticle",
+ "author": [{"@type": "Person", "name": "Alice"}, {"name": "Bob"}]}]}
+</script></head>
+<body>
+<nav><a href="/">Home</a> <a href="/news">News</a></nav>
+<div class="header">Example News, the best news.</div>
+<div id="main"><article class="post">
+<h1>A Story</h1>
+<p>This is the first paragraph of the story, which is long enough to
+count, and it has a <a href="https://example.com/">link</a>.</p>
+<div class="share-buttons"><a href="#">Share on this, that and whatever else</a></div>
+<p>The second paragraph is <em>also</em> long, with <b>bold</b> text, commas,
+and a few more words to make it score well.</p>
+<ul><li>One item of a list</li><li>Another item, with some text</li></ul>
+<blockquote><p>Some quoted text, which someone said somewhere.</p></blockquote>
+<pre>let x = 1;
+let y = 2;</pre>
+</article></div>
+<aside class="sidebar"><p>Related stories that are long enough to be considered as
+paragraphs by the algorithm, but should be ignored.</p></aside>
+<footer><p>Copyright Example News. All rights reserved, now and forever.</p></footer>
+</body></html>"##;
+
+    #[test]
+    fn extraction()
+    {
+        let article = extract(PAGE).unwrap();
+        assert_eq!(article.title.as_deref(), Some("A Story"));
+        assert_eq!(article.authors, vec![String::from("Alice"), String::from("Bob")]);
+        assert_eq!(article.published.as_deref(), Some("2023-04-05T06:07:08Z"));
+        assert!(article.content.starts_with("<p>This is the first paragraph"));
+        assert!(article.content.contains("<a href=\"https://example.com/\">link</a>"));
+        assert!(!article.content.contains("<h1>"));
+        assert!(!article.content.contains("Share on"));
+        assert!(!article.content.contains("Home"));
+        assert!(!article.content.contains("Related stories"));
+        assert!(!article.content.contains("Copyright"));
+        assert!(extract("<html><body><p>Too short.</p></body></html>").is_none());
+    }
+
+    #[test]
+    fn markdown()
+    {
+        let article = extract(PAGE).unwrap();
+        assert_eq!(renderMarkdown(&article), r#"# A Story
+
+*Alice, Bob · 2023-04-05T06:07:08Z*
+
+This is the first paragraph of the story, which is long enough to count, and it has a [link](https://example.com/).
+
+The second paragraph is *also* long, with **bold** text, commas, and a few more words to make it score well.
+
+- One item of a list
+- Another item, with some text
+
+> Some quoted text, which someone said somewhere.
+
+```
+let x = 1;
+let y = 2;
+```
+"#);
+    }
+}
@@ -0,0 +1,265 @@
+use std::path::Path;
+
+use log::{info, debug};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use url::Url;
+
+use crate::analyser;
+use crate::analyser::{Analysis, RecordInfo, ResourceInfo, TempItem, UrlRule};
+use crate::error::Error;
+use crate::html;
+
+/// The prefix of the DOIs of arXiv papers.
+static ARXIV_DOI_PREFIX: &str = "10.48550/arxiv.";
+
+/// The URLs handled by the arXiv client.
+pub fn urlRules() -> Result<Vec<UrlRule>, Error>
+{
+    Ok(vec![
+        UrlRule::withPath("arxiv.org", r"^/(abs|pdf)/.")?,
+        UrlRule::withPath("*.arxiv.org", r"^/(abs|pdf)/.")?,
+        UrlRule::withPath("doi.org", r"(?i)^/10\.48550/arxiv\.")?,
+        UrlRule::withPath("*.doi.org", r"(?i)^/10\.48550/arxiv\.")?,
+    ])
+}
+
+/// Get the arXiv ID (with the version if there is one) of the paper at
+/// `url`.
+fn paperId(url: &str) -> Result<String, Error>
+{
+    let u = Url::parse(url).map_err(|_| rterr!("Invalid URL: {}", url))?;
+    let path = percent_encoding::percent_decode_str(u.path()).decode_utf8()
+        .map_err(|_| rterr!("Invalid URL: {}", url))?;
+    let path = path.trim_end_matches('/');
+    let id = if let Some(id) = path.strip_prefix("/abs/")
+    {
+        id
+    }
+    else if let Some(id) = path.strip_prefix("/pdf/")
+    {
+        id.trim_end_matches(".pdf")
+    }
+    else if path.to_lowercase().starts_with(&format!("/{}", ARXIV_DOI_PREFIX))
+    {
+        &path[ARXIV_DOI_PREFIX.len() + 1..]
+    }
+    else
+    {
+        ""
+    };
+    if id.is_empty()
+    {
+        return Err(rterr!("Not an arXiv paper: {}", url));
+    }
+    Ok(id.to_owned())
+}
+
+/// The information of a paper, from the arXiv API.
+#[derive(Default, Debug, PartialEq)]
+struct Paper
+{
+    title: String,
+    authors: Vec<String>,
+    summary: String,
+    published: Option<String>,
+    doi: Option<String>,
+    journal_ref: Option<String>,
+}
+
+/// Collapse the line breaks and indentation in the text of the arXiv
+/// API.
+fn normalizeSpace(s: &str) -> String
+{
+    s.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// Parse the Atom feed from the arXiv API, which should have one entry.
+fn parseFeed(feed: &str) -> Result<Paper, Error>
+{
+    let mut reader = Reader::from_str(feed);
+    reader.trim_text(true);
+    let mut paper = Paper::default();
+    let mut path: Vec<String> = Vec::new();
+    let mut found = false;
+    loop
+    {
+        match reader.read_event()
+        {
+            Ok(Event::Start(e)) =>
+                path.push(String::from_utf8_lossy(e.name().as_ref()).into_owned()),
+            Ok(Event::End(_)) =>
+            {
+                if path.pop().as_deref() == Some("entry")
+                {
+                    break;
+                }
+            },
+            Ok(Event::Text(t)) =>
+            {
+                let text = t.unescape().map_err(
+                    |_| rterr!("Invalid text in arXiv response"))?;
+                let names: Vec<&str> = path.iter().map(|n| n.as_str()).collect();
+                match names[..]
+                {
+                    [.., "entry", "title"] => paper.title = normalizeSpace(&text),
+                    [.., "entry", "summary"] => paper.summary = normalizeSpace(&text),
+                    [.., "entry", "published"] => paper.published = Some(text.into_owned()),
+                    [.., "entry", "author", "name"] => paper.authors.push(text.into_owned()),
+                    [.., "entry", "arxiv:doi"] => paper.doi = Some(text.into_owned()),
+                    [.., "entry", "arxiv:journal_ref"] =>
+                        paper.journal_ref = Some(normalizeSpace(&text)),
+                    [.., "entry", "id"] => found = true,
+                    _ => {},
+                }
+            },
+            Ok(Event::Eof) => break,
+            Ok(_) => {},
+            Err(e) => return Err(rterr!("Invalid arXiv response: {}", e)),
+        }
+    }
+    // The API returns an entry titled “Error” for invalid IDs, which
+    // has no author.
+    if !found || paper.authors.is_empty()
+    {
+        return Err(rterr!("Paper not found in arXiv response"));
+    }
+    Ok(paper)
+}
+
+fn renderAbstract(id: &str, paper: &Paper) -> String
+{
+    let mut body = format!(
+        "<h1>{}</h1>\n<div class=\"meta\">{}</div>\n<div class=\"meta\">arXiv:{} · {}</div>\n",
+        html::escape(&paper.title), html::escape(&paper.authors.join(", ")),
+        html::escape(id), html::escape(paper.published.as_deref().unwrap_or("")));
+    if let Some(j) = &paper.journal_ref
+    {
+        body.push_str(&format!("<div class=\"meta\">{}</div>\n", html::escape(j)));
+    }
+    if let Some(doi) = &paper.doi
+    {
+        body.push_str(&format!(
+            "<div class=\"meta\">DOI: <a href=\"https://doi.org/{0}\">{0}</a></div>\n",
+            html::escape(doi)));
+    }
+    body.push_str(&format!(
+        "<h2>Abstract</h2>\n<blockquote>{}</blockquote>\n\
+         <p><a href=\"https://arxiv.org/pdf/{1}\">PDF</a> · \
+         <a href=\"https://arxiv.org/abs/{1}\">arXiv</a></p>\n",
+        html::escape(&paper.summary), html::escape(id)));
+    html::page(&paper.title, &body)
+}
+
+fn getText(url: &str) -> Result<String, Error>
+{
+    debug!("Getting {}...", url);
+    ureq::get(url).call().map_err(|e| rterr!("Failed to get {}: {}", url, e))?
+        .into_string().map_err(|e| rterr!("Failed to read response from {}: {}", url, e))
+}
+
+pub struct Client {}
+
+impl Client
+{
+    pub fn new() -> Self
+    {
+        Self {}
+    }
+}
+
+impl analyser::ResourceAnalyser for Client
+{
+    fn analyse(&self, url: &str, scratch: &Path) -> Result<Analysis, Error>
+    {
+        let id = paperId(url)?;
+        let feed = getText(&format!("https://export.arxiv.org/api/query?id_list={}",
+                                    id))?;
+        let paper = parseFeed(&feed)?;
+        info!("Found paper “{}”.", paper.title);
+        let filename = id.replace('/', "_");
+
+        let mut analysis = Analysis::new();
+        analysis.info = RecordInfo {
+            title: Some(paper.title.clone()),
+            authors: paper.authors.clone(),
+            published: paper.published.clone(),
+        };
+        let pdf_url = format!("https://arxiv.org/pdf/{}", id);
+        analysis.addWithInfo(TempItem::Url(pdf_url.clone()), ResourceInfo {
+            role: Some(String::from("paper")),
+            content_type: Some(String::from("application/pdf")),
+            original_name: Some(format!("{}.pdf", filename)),
+            ..ResourceInfo::default()
+        });
+        analysis.addWithRole(TempItem::Html(renderAbstract(&id, &paper)), "abstract");
+
+        let bibtex_url = format!("https://arxiv.org/bibtex/{}", id);
+        let bibtex = scratch.join(format!("{}.bib", filename));
+        std::fs::write(&bibtex, getText(&bibtex_url)?).map_err(
+            |e| rterr!("Failed to write {:?}: {}", bibtex, e))?;
+        analysis.addWithInfo(TempItem::File(bibtex), ResourceInfo {
+            role: Some(String::from("bibtex")),
+            url: Some(bibtex_url),
+            original_name: Some(format!("{}.bib", filename)),
+            ..ResourceInfo::default()
+        });
+
+        let feed_file = scratch.join(format!("{}.xml", filename));
+        std::fs::write(&feed_file, feed).map_err(
+            |e| rterr!("Failed to write {:?}: {}", feed_file, e))?;
+        analysis.addWithRole(TempItem::File(feed_file), "raw");
+        Ok(analysis)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn urls() -> Result<()>
+    {
+        assert_eq!(paperId("https://arxiv.org/abs/1706.03762v7")?, "1706.03762v7");
+        assert_eq!(paperId("https://arxiv.org/pdf/1706.03762.pdf")?, "1706.03762");
+        assert_eq!(paperId("https://export.arxiv.org/abs/hep-th/9901001")?,
+                   "hep-th/9901001");
+        assert_eq!(paperId("https://doi.org/10.48550/arXiv.1706.03762")?, "1706.03762");
+        assert!(paperId("https://arxiv.org/list/cs.CL/recent").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn feed() -> Result<()>
+    {
+        let feed = r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title type="html">ArXiv Query: id_list=1706.03762</title>
+  <entry>
+    <id>http://arxiv.org/abs/1706.03762v7</id>
+    <published>2017-06-12T17:57:34Z</published>
+    <title>Attention Is All
+      You Need</title>
+    <summary>  The dominant sequence transduction models &amp; more.
+    </summary>
+    <author><name>Ashish Vaswani</name></author>
+    <author><name>Noam Shazeer</name></author>
+    <arxiv:doi xmlns:arxiv="http://arxiv.org/schemas/atom">10.1000/xyz</arxiv:doi>
+  </entry>
+</feed>"#;
+        let paper = parseFeed(feed)?;
+        assert_eq!(paper.title, "Attention Is All You Need");
+        assert_eq!(paper.authors, vec![String::from("Ashish Vaswani"),
+                                       String::from("Noam Shazeer")]);
+        assert_eq!(paper.summary, "The dominant sequence transduction models & more.");
+        assert_eq!(paper.published.as_deref(), Some("2017-06-12T17:57:34Z"));
+        assert_eq!(paper.doi.as_deref(), Some("10.1000/xyz"));
+        assert!(renderAbstract("1706.03762v7", &paper).contains("<h1>Attention Is All You Need</h1>"));
+
+        assert!(parseFeed(r#"<feed><entry><id>x</id><title>Error</title></entry></feed>"#)
+                .is_err());
+        Ok(())
+    }
+}
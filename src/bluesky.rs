@@ -0,0 +1,393 @@
+use std::path::Path;
+
+use log::{info, debug};
+use serde_json::Value;
+use url::Url;
+
+use crate::analyser;
+use crate::analyser::{Analysis, RecordInfo, ResourceInfo, TempItem, UrlRule};
+use crate::error::Error;
+use crate::html;
+
+static XRPC_BASE: &str = "https://public.api.bsky.app/xrpc";
+
+/// The URLs handled by the Bluesky client.
+pub fn urlRules() -> Result<Vec<UrlRule>, Error>
+{
+    Ok(vec![
+        UrlRule::withPath("bsky.app", r"^/profile/[^/]+/post/[^/]+/?$")?,
+    ])
+}
+
+/// Get the handle (or DID) of the author and the record key of the
+/// post at `url`.
+fn postId(url: &str) -> Result<(String, String), Error>
+{
+    let u = Url::parse(url).map_err(|_| rterr!("Invalid URL: {}", url))?;
+    let parts: Vec<&str> = u.path().trim_matches('/').split('/').collect();
+    match parts[..]
+    {
+        ["profile", actor, "post", rkey] => Ok((actor.to_owned(), rkey.to_owned())),
+        _ => Err(rterr!("Not a Bluesky post URL: {}", url)),
+    }
+}
+
+fn getJson(url: &str) -> Result<Value, Error>
+{
+    debug!("Getting {}...", url);
+    let res = ureq::get(url).call()
+        .map_err(|e| rterr!("Failed to get {}: {}", url, e))?;
+    let body = res.into_string().map_err(
+        |e| rterr!("Failed to read response from {}: {}", url, e))?;
+    serde_json::from_str(&body)
+        .map_err(|_| rterr!("Invalid JSON response from {}", url))
+}
+
+/// Resolve `actor` into a DID, if it is a handle.
+fn resolveDid(actor: &str) -> Result<String, Error>
+{
+    if actor.starts_with("did:")
+    {
+        return Ok(actor.to_owned());
+    }
+    let data = getJson(&format!(
+        "{}/com.atproto.identity.resolveHandle?handle={}", XRPC_BASE, actor))?;
+    data["did"].as_str().map(|s| s.to_owned())
+        .ok_or_else(|| rterr!("Failed to resolve Bluesky handle {}", actor))
+}
+
+/// Get the link of a facet feature, e.g. the URL of a link, or the
+/// profile of a mention.
+fn featureLink(feature: &Value) -> Option<String>
+{
+    match feature["$type"].as_str()?
+    {
+        "app.bsky.richtext.facet#link" => feature["uri"].as_str().map(|s| s.to_owned()),
+        "app.bsky.richtext.facet#mention" => feature["did"].as_str()
+            .map(|did| format!("https://bsky.app/profile/{}", did)),
+        "app.bsky.richtext.facet#tag" => feature["tag"].as_str()
+            .map(|tag| format!("https://bsky.app/hashtag/{}", tag)),
+        _ => None,
+    }
+}
+
+/// Facets in the text of post `record`, as the byte range in the text,
+/// the link, and whether the link is a URL. Facets that overlap or
+/// are not valid ranges are skipped.
+fn facets(record: &Value) -> Vec<(usize, usize, String, bool)>
+{
+    let text = record["text"].as_str().unwrap_or("");
+    let mut result: Vec<(usize, usize, String, bool)> = Vec::new();
+    for facet in record["facets"].as_array().into_iter().flatten()
+    {
+        let start = facet["index"]["byteStart"].as_u64().unwrap_or(0) as usize;
+        let end = facet["index"]["byteEnd"].as_u64().unwrap_or(0) as usize;
+        if start >= end || end > text.len() || !text.is_char_boundary(start) ||
+            !text.is_char_boundary(end)
+        {
+            continue;
+        }
+        let feature = &facet["features"][0];
+        if let Some(link) = featureLink(feature)
+        {
+            let is_url = feature["$type"].as_str() == Some("app.bsky.richtext.facet#link");
+            result.push((start, end, link, is_url));
+        }
+    }
+    result.sort_by_key(|f| f.0);
+    let mut last_end = 0;
+    result.retain(|f| {
+        let keep = f.0 >= last_end;
+        if keep
+        {
+            last_end = f.1;
+        }
+        keep
+    });
+    result
+}
+
+/// Get the text of post `record`, with the shortened links replaced by
+/// their full URLs.
+fn postText(record: &Value) -> String
+{
+    let text = record["text"].as_str().unwrap_or("");
+    let mut result = String::new();
+    let mut pos = 0;
+    for (start, end, link, is_url) in facets(record)
+    {
+        result.push_str(&text[pos..start]);
+        result.push_str(if is_url {&link} else {&text[start..end]});
+        pos = end;
+    }
+    result.push_str(&text[pos..]);
+    result
+}
+
+/// Render the text of post `record` into HTML, with the facets as
+/// links.
+fn renderText(record: &Value) -> String
+{
+    let text = record["text"].as_str().unwrap_or("");
+    let mut result = String::new();
+    let mut pos = 0;
+    for (start, end, link, _) in facets(record)
+    {
+        result.push_str(&html::escape(&text[pos..start]));
+        result.push_str(&format!("<a href=\"{}\">{}</a>", html::escape(&link),
+                                 html::escape(&text[start..end])));
+        pos = end;
+    }
+    result.push_str(&html::escape(&text[pos..]));
+    result.replace('\n', "<br>\n")
+}
+
+/// The images in the embed view `embed` of a post.
+fn embedImages(embed: &Value) -> Vec<&Value>
+{
+    match embed["$type"].as_str()
+    {
+        Some("app.bsky.embed.images#view") =>
+            embed["images"].as_array().into_iter().flatten().collect(),
+        Some("app.bsky.embed.recordWithMedia#view") => embedImages(&embed["media"]),
+        _ => Vec::new(),
+    }
+}
+
+/// The quoted post in the embed view `embed` of a post, as its author,
+/// record and embed view.
+fn embedQuote(embed: &Value) -> Option<(&Value, &Value, &Value)>
+{
+    let quoted = match embed["$type"].as_str()?
+    {
+        "app.bsky.embed.record#view" => &embed["record"],
+        "app.bsky.embed.recordWithMedia#view" => &embed["record"]["record"],
+        _ => return None,
+    };
+    if quoted["$type"].as_str() != Some("app.bsky.embed.record#viewRecord")
+    {
+        return None;
+    }
+    Some((&quoted["author"], &quoted["value"], &quoted["embeds"][0]))
+}
+
+fn imageInfo(image: &Value) -> ResourceInfo
+{
+    let ratio = &image["aspectRatio"];
+    ResourceInfo {
+        description: image["alt"].as_str().filter(|s| !s.is_empty())
+            .map(|s| s.to_owned()),
+        aspect_ratio: match (ratio["width"].as_u64(), ratio["height"].as_u64())
+        {
+            (Some(w), Some(h)) => Some(format!("{}:{}", w, h)),
+            _ => None,
+        },
+        ..ResourceInfo::default()
+    }
+}
+
+/// Add the text and images of a post to `analysis`, along with the
+/// post it quotes.
+fn addPost(record: &Value, embed: &Value, relation: Option<&str>,
+           analysis: &mut Analysis)
+{
+    let relation = relation.map(|r| r.to_owned());
+    analysis.addWithInfo(TempItem::Text(postText(record)), ResourceInfo {
+        relation: relation.clone(),
+        ..ResourceInfo::default()
+    });
+    for image in embedImages(embed)
+    {
+        if let Some(u) = image["fullsize"].as_str()
+        {
+            info!("Found Bluesky image at {}.", u);
+            analysis.addWithInfo(TempItem::Url(u.to_owned()), ResourceInfo {
+                relation: relation.clone(),
+                ..imageInfo(image)
+            });
+        }
+    }
+    if let Some((_, record, embed)) = embedQuote(embed)
+    {
+        addPost(record, embed, Some("quoted"), analysis);
+    }
+}
+
+fn renderPost(author: &Value, record: &Value, embed: &Value) -> String
+{
+    let handle = author["handle"].as_str().unwrap_or("");
+    let mut result = String::from("<div class=\"post\">\n<div class=\"author\">");
+    if let Some(avatar) = author["avatar"].as_str()
+    {
+        result.push_str(&format!("<img class=\"avatar\" src=\"{}\"> ",
+                                 html::escape(avatar)));
+    }
+    result.push_str(&format!(
+        "<b>{}</b> <span class=\"meta\">@{}</span></div>\n<p>{}</p>\n",
+        html::escape(author["displayName"].as_str().unwrap_or(handle)),
+        html::escape(handle), renderText(record)));
+    for image in embedImages(embed)
+    {
+        if let Some(u) = image["fullsize"].as_str()
+        {
+            let alt = image["alt"].as_str().map(
+                |a| format!(" alt=\"{}\"", html::escape(a))).unwrap_or_default();
+            result.push_str(&format!("<p><img src=\"{}\"{}></p>\n",
+                                     html::escape(u), alt));
+        }
+    }
+    if embed["$type"].as_str() == Some("app.bsky.embed.external#view")
+    {
+        let external = &embed["external"];
+        if let Some(u) = external["uri"].as_str()
+        {
+            result.push_str(&format!(
+                "<blockquote><a href=\"{}\">{}</a><br>\n{}</blockquote>\n",
+                html::escape(u),
+                html::escape(external["title"].as_str().unwrap_or(u)),
+                html::escape(external["description"].as_str().unwrap_or(""))));
+        }
+    }
+    if let Some((author, record, embed)) = embedQuote(embed)
+    {
+        result.push_str(&format!("<blockquote>\n{}</blockquote>\n",
+                                 renderPost(author, record, embed)));
+    }
+    result.push_str(&format!("<div class=\"meta\">{}</div>\n</div>\n",
+                             html::escape(record["createdAt"].as_str().unwrap_or(""))));
+    result
+}
+
+/// Collect the avatar URLs of `author` and the author of the post
+/// quoted in `embed` into `avatars`, without duplication.
+fn collectAvatars(author: &Value, embed: &Value, avatars: &mut Vec<String>)
+{
+    if let Some(a) = author["avatar"].as_str()
+    {
+        if !avatars.iter().any(|x| x == a)
+        {
+            avatars.push(a.to_owned());
+        }
+    }
+    if let Some((author, _, embed)) = embedQuote(embed)
+    {
+        collectAvatars(author, embed, avatars);
+    }
+}
+
+pub struct Client {}
+
+impl Client
+{
+    pub fn new() -> Self
+    {
+        Self {}
+    }
+}
+
+impl analyser::ResourceAnalyser for Client
+{
+    fn analyse(&self, url: &str, _scratch: &Path) -> Result<Analysis, Error>
+    {
+        let (actor, rkey) = postId(url)?;
+        let did = resolveDid(&actor)?;
+        let data = getJson(&format!(
+            "{}/app.bsky.feed.getPostThread?uri=at://{}/app.bsky.feed.post/{}&depth=0&parentHeight=0",
+            XRPC_BASE, did, rkey))?;
+        let post = &data["thread"]["post"];
+        if !post.is_object()
+        {
+            return Err(rterr!("Failed to find Bluesky post at {}", url));
+        }
+        let (author, record, embed) = (&post["author"], &post["record"], &post["embed"]);
+
+        let mut analysis = Analysis::new();
+        analysis.info = RecordInfo {
+            title: None,
+            authors: author["handle"].as_str()
+                .map(|h| vec![format!("@{}", h)]).unwrap_or_default(),
+            published: record["createdAt"].as_str().map(|s| s.to_owned()),
+        };
+        addPost(record, embed, None, &mut analysis);
+        let mut avatars = Vec::new();
+        collectAvatars(author, embed, &mut avatars);
+        for avatar in avatars
+        {
+            analysis.addWithRole(TempItem::Url(avatar), "avatar");
+        }
+        let raw = serde_json::to_string_pretty(post)
+            .map_err(|_| rterr!("Failed to serialize post"))?;
+        analysis.addWithRole(TempItem::Json(raw), "raw");
+        let title = format!("{} on Bluesky", author["displayName"].as_str()
+                            .or_else(|| author["handle"].as_str()).unwrap_or(""));
+        analysis.addWithRole(TempItem::Html(html::page(
+            &title, &renderPost(author, record, embed))), "page");
+        Ok(analysis)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn urls() -> Result<()>
+    {
+        assert_eq!(postId("https://bsky.app/profile/jay.bsky.team/post/3juzlwllznd24")?,
+                   (String::from("jay.bsky.team"), String::from("3juzlwllznd24")));
+        assert!(postId("https://bsky.app/profile/jay.bsky.team").is_err());
+        assert_eq!(resolveDid("did:plc:abc")?, "did:plc:abc");
+        Ok(())
+    }
+
+    #[test]
+    fn richText()
+    {
+        // “é” is two bytes in UTF-8.
+        let record = serde_json::json!({
+            "text": "Café example.com/a… #rust <3",
+            "facets": [
+                {"index": {"byteStart": 6, "byteEnd": 22},
+                 "features": [{"$type": "app.bsky.richtext.facet#link",
+                               "uri": "https://example.com/a/long/path"}]},
+                {"index": {"byteStart": 23, "byteEnd": 28},
+                 "features": [{"$type": "app.bsky.richtext.facet#tag", "tag": "rust"}]}]});
+        assert_eq!(postText(&record), "Café https://example.com/a/long/path #rust <3");
+        assert_eq!(renderText(&record),
+                   "Café <a href=\"https://example.com/a/long/path\">example.com/a…</a> \
+                    <a href=\"https://bsky.app/hashtag/rust\">#rust</a> &lt;3");
+    }
+
+    #[test]
+    fn embeds()
+    {
+        let image = serde_json::json!({
+            "$type": "app.bsky.embed.images#view",
+            "images": [{"fullsize": "https://cdn.bsky.app/a.jpg", "alt": "A cat",
+                        "aspectRatio": {"width": 4, "height": 3}}]});
+        let embed = serde_json::json!({
+            "$type": "app.bsky.embed.recordWithMedia#view",
+            "media": image,
+            "record": {"record": {
+                "$type": "app.bsky.embed.record#viewRecord",
+                "author": {"handle": "b.bsky.social", "avatar": "https://cdn.bsky.app/b.jpg"},
+                "value": {"text": "Quoted"},
+                "embeds": [image]}}});
+        let author = serde_json::json!({"handle": "a.bsky.social"});
+        let record = serde_json::json!({"text": "Look"});
+        let mut analysis = Analysis::new();
+        addPost(&record, &embed, None, &mut analysis);
+        let relations: Vec<Option<&str>> = analysis.resources.iter()
+            .map(|r| r.info.relation.as_deref()).collect();
+        assert_eq!(relations, vec![None, None, Some("quoted"), Some("quoted")]);
+        assert_eq!(analysis.resources[1].info.description.as_deref(), Some("A cat"));
+        assert_eq!(analysis.resources[1].info.aspect_ratio.as_deref(), Some("4:3"));
+        assert_eq!(analysis.resources[2].item, TempItem::Text(String::from("Quoted")));
+
+        let mut avatars = Vec::new();
+        collectAvatars(&author, &embed, &mut avatars);
+        assert_eq!(avatars, vec![String::from("https://cdn.bsky.app/b.jpg")]);
+    }
+}
@@ -0,0 +1,405 @@
+//! Rendering web pages with a locally installed Chromium, driven over
+//! the DevTools protocol. This is for pages that are empty until
+//! their JavaScript runs.
+
+use std::collections::{HashSet, VecDeque};
+use std::io::{BufRead, BufReader};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+use serde_json::{json, Value};
+use tungstenite::client::client_with_config;
+use tungstenite::protocol::WebSocketConfig;
+use tungstenite::{Message, WebSocket};
+use url::Url;
+
+use crate::config::ChromiumConfig;
+use crate::error::Error;
+
+/// The names of the Chromium executable to look for in `PATH`.
+static CHROMIUM_NAMES: &[&str] = &["chromium", "chromium-browser", "google-chrome",
+                                   "google-chrome-stable"];
+/// How long to wait for Chromium to start.
+const LAUNCH_TIMEOUT: Duration = Duration::from_secs(20);
+/// The network is considered idle after there has been no request
+/// for this long.
+const IDLE_TIME: Duration = Duration::from_millis(500);
+/// How long to block on the socket at a time.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How long to wait for each command after the page is loaded, such as
+/// serializing the DOM or printing the PDF. These do not share the
+/// timeout of loading, which may well be used up by a page that is
+/// never idle.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Types of requests that stay open as long as the page, and so never
+/// finish loading.
+static LONG_LIVED_REQUESTS: &[&str] = &["WebSocket", "EventSource"];
+
+/// Serialize the DOM, with the doctype.
+static SERIALIZE_DOM: &str = "(document.doctype ? \
+    new XMLSerializer().serializeToString(document.doctype) : '') + \
+    document.documentElement.outerHTML";
+
+/// The result of rendering a page.
+pub struct Rendering
+{
+    /// The URL of the page after redirections.
+    pub url: String,
+    /// The serialized DOM.
+    pub html: String,
+    pub pdf: Option<Vec<u8>>,
+    /// A PNG screenshot of the full page.
+    pub screenshot: Option<Vec<u8>>,
+}
+
+/// Find the Chromium executable.
+fn findChromium(conf: &ChromiumConfig) -> Result<PathBuf, Error>
+{
+    if let Some(path) = &conf.path
+    {
+        return Ok(PathBuf::from(path));
+    }
+    let paths = std::env::var_os("PATH").unwrap_or_default();
+    std::env::split_paths(&paths)
+        .flat_map(|dir| CHROMIUM_NAMES.iter().map(move |name| dir.join(name)))
+        .find(|path| path.is_file())
+        .ok_or_else(|| rterr!("Chromium is not found. Set its path in the config."))
+}
+
+/// Get the DevTools URL from a line of the output of Chromium.
+fn devToolsUrl(line: &str) -> Option<&str>
+{
+    line.strip_prefix("DevTools listening on ").map(str::trim)
+}
+
+/// Whether the request in the `Network.requestWillBeSent` event
+/// `event` stays open as long as the page.
+fn isLongLived(event: &Value) -> bool
+{
+    event["params"]["type"].as_str().is_some_and(|t| LONG_LIVED_REQUESTS.contains(&t))
+}
+
+fn isTimeout(e: &tungstenite::Error) -> bool
+{
+    match e
+    {
+        tungstenite::Error::Io(e) => e.kind() == std::io::ErrorKind::WouldBlock ||
+            e.kind() == std::io::ErrorKind::TimedOut,
+        _ => false,
+    }
+}
+
+/// A running Chromium. It is killed when dropped.
+struct Browser
+{
+    process: Child,
+    socket: WebSocket<TcpStream>,
+    next_id: u64,
+    /// Events received while waiting for the result of a command.
+    events: VecDeque<Value>,
+}
+
+impl Browser
+{
+    fn launch(conf: &ChromiumConfig, profile: &Path) -> Result<Self, Error>
+    {
+        let program = findChromium(conf)?;
+        debug!("Launching {:?}...", program);
+        let mut process = Command::new(&program)
+            .args(["--headless=new", "--disable-gpu", "--no-first-run",
+                   "--no-default-browser-check", "--hide-scrollbars", "--mute-audio",
+                   "--remote-debugging-port=0", "--window-size=1280,1024"])
+            .arg(format!("--user-data-dir={}", profile.display()))
+            .arg("about:blank")
+            .stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::piped())
+            .spawn().map_err(|e| rterr!("Failed to run {:?}: {}", program, e))?;
+
+        // Chromium prints the DevTools URL to stderr. Keep reading
+        // stderr afterwards, so that it does not block on a full pipe.
+        let stderr = process.stderr.take().unwrap();
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stderr).lines().map_while(Result::ok)
+            {
+                if let Some(u) = devToolsUrl(&line)
+                {
+                    let _ = sender.send(u.to_owned());
+                }
+            }
+        });
+        let ws_url = match receiver.recv_timeout(LAUNCH_TIMEOUT)
+        {
+            Ok(u) => u,
+            Err(_) =>
+            {
+                let _ = process.kill();
+                let _ = process.wait();
+                return Err(rterr!("Chromium did not start in time"));
+            },
+        };
+
+        // Chromium would be left running if anything below fails,
+        // so it is wrapped first.
+        let connect = || -> Result<WebSocket<TcpStream>, Error> {
+            let u = Url::parse(&ws_url).map_err(|_| rterr!("Invalid DevTools URL: {}", ws_url))?;
+            let addr = u.socket_addrs(|| None).map_err(
+                |e| rterr!("Invalid DevTools URL {}: {}", ws_url, e))?;
+            let stream = TcpStream::connect(&addr[..]).map_err(
+                |e| rterr!("Failed to connect to Chromium: {}", e))?;
+            // Screenshots and PDFs come in large messages.
+            let config = WebSocketConfig::default().max_message_size(None)
+                .max_frame_size(None);
+            let (socket, _) = client_with_config(ws_url.as_str(), stream, Some(config))
+                .map_err(|e| rterr!("Failed to connect to Chromium: {}", e))?;
+            // Reading with a timeout allows waiting for events until
+            // a deadline.
+            socket.get_ref().set_read_timeout(Some(POLL_INTERVAL)).map_err(
+                |e| rterr!("Failed to set up connection to Chromium: {}", e))?;
+            Ok(socket)
+        };
+        match connect()
+        {
+            Ok(socket) => Ok(Self { process, socket, next_id: 0, events: VecDeque::new() }),
+            Err(e) =>
+            {
+                let _ = process.kill();
+                let _ = process.wait();
+                Err(e)
+            },
+        }
+    }
+
+    /// Receive a message. Return `None` if nothing arrives before
+    /// `deadline`.
+    fn receive(&mut self, deadline: Instant) -> Result<Option<Value>, Error>
+    {
+        loop
+        {
+            match self.socket.read()
+            {
+                Ok(Message::Text(text)) =>
+                    return serde_json::from_str(text.as_str()).map(Some).map_err(
+                        |e| rterr!("Invalid message from Chromium: {}", e)),
+                Ok(_) => {},
+                Err(e) if isTimeout(&e) =>
+                {
+                    if Instant::now() >= deadline
+                    {
+                        return Ok(None);
+                    }
+                },
+                Err(e) => return Err(rterr!("Lost connection to Chromium: {}", e)),
+            }
+        }
+    }
+
+    /// Get the next event, either one that was received before, or a
+    /// new one.
+    fn nextEvent(&mut self, deadline: Instant) -> Result<Option<Value>, Error>
+    {
+        match self.events.pop_front()
+        {
+            Some(event) => Ok(Some(event)),
+            None => self.receive(deadline),
+        }
+    }
+
+    /// Run a DevTools command, and return its result.
+    fn call(&mut self, method: &str, params: Value, session: Option<&str>,
+            deadline: Instant) -> Result<Value, Error>
+    {
+        self.next_id += 1;
+        let id = self.next_id;
+        let mut command = json!({"id": id, "method": method, "params": params});
+        if let Some(s) = session
+        {
+            command["sessionId"] = json!(s);
+        }
+        self.socket.send(Message::Text(command.to_string().into())).map_err(
+            |e| rterr!("Failed to send {} to Chromium: {}", method, e))?;
+        loop
+        {
+            let message = self.receive(deadline)?.ok_or_else(
+                || rterr!("Timed out waiting for {} in Chromium", method))?;
+            if message["id"].as_u64() != Some(id)
+            {
+                self.events.push_back(message);
+                continue;
+            }
+            if let Some(error) = message.get("error")
+            {
+                return Err(rterr!("{} failed in Chromium: {}", method,
+                                  error["message"].as_str().unwrap_or("")));
+            }
+            return Ok(message["result"].clone());
+        }
+    }
+
+    /// Wait until the page in `session` is loaded, and there has been
+    /// no network activity for a while.
+    fn waitForIdle(&mut self, session: &str, deadline: Instant) -> Result<(), Error>
+    {
+        let mut loaded = false;
+        let mut requests: HashSet<String> = HashSet::new();
+        let mut idle_since: Option<Instant> = None;
+        loop
+        {
+            let now = Instant::now();
+            if now >= deadline
+            {
+                warn!("Timed out waiting for the page to finish loading. \
+                       Saving it as it is.");
+                return Ok(());
+            }
+            if let Some(event) = self.nextEvent((now + POLL_INTERVAL).min(deadline))?
+            {
+                if event["sessionId"].as_str() == Some(session)
+                {
+                    let request = event["params"]["requestId"].as_str().unwrap_or("")
+                        .to_owned();
+                    match event["method"].as_str().unwrap_or("")
+                    {
+                        "Page.loadEventFired" => loaded = true,
+                        "Network.requestWillBeSent" if !isLongLived(&event) =>
+                        {
+                            requests.insert(request);
+                        },
+                        "Network.loadingFinished" | "Network.loadingFailed" =>
+                        {
+                            requests.remove(&request);
+                        },
+                        _ => {},
+                    }
+                }
+            }
+            if loaded && requests.is_empty()
+            {
+                if Instant::now() - *idle_since.get_or_insert(now) >= IDLE_TIME
+                {
+                    return Ok(());
+                }
+            }
+            else
+            {
+                idle_since = None;
+            }
+        }
+    }
+}
+
+impl Drop for Browser
+{
+    fn drop(&mut self)
+    {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+fn decodeData(result: &Value, what: &str) -> Result<Vec<u8>, Error>
+{
+    base64::decode(result["data"].as_str().unwrap_or("")).map_err(
+        |e| rterr!("Invalid {} from Chromium: {}", what, e))
+}
+
+/// Render `url` in Chromium. The profile of Chromium is created in
+/// `scratch`.
+pub fn render(url: &str, conf: &ChromiumConfig, scratch: &Path) -> Result<Rendering, Error>
+{
+    let deadline = Instant::now() + Duration::from_secs(conf.timeout);
+    let mut browser = Browser::launch(conf, &scratch.join("chromium-profile"))?;
+    let target = browser.call("Target.createTarget", json!({"url": "about:blank"}),
+                              None, deadline)?;
+    let session_id = browser.call(
+        "Target.attachToTarget", json!({"targetId": target["targetId"], "flatten": true}),
+        None, deadline)?["sessionId"].as_str()
+        .ok_or_else(|| rterr!("Failed to attach to the page in Chromium"))?.to_owned();
+    let session = Some(session_id.as_str());
+    browser.call("Page.enable", json!({}), session, deadline)?;
+    browser.call("Network.enable", json!({}), session, deadline)?;
+
+    info!("Rendering {} in Chromium...", url);
+    let navigation = browser.call("Page.navigate", json!({"url": url}), session, deadline)?;
+    if let Some(error) = navigation["errorText"].as_str().filter(|e| !e.is_empty())
+    {
+        return Err(rterr!("Chromium failed to load {}: {}", url, error));
+    }
+    browser.waitForIdle(&session_id, deadline)?;
+
+    let evaluate = |browser: &mut Browser, expression: &str| -> Result<String, Error> {
+        let result = browser.call("Runtime.evaluate",
+                                  json!({"expression": expression, "returnByValue": true}),
+                                  session, Instant::now() + COMMAND_TIMEOUT)?;
+        result["result"]["value"].as_str().map(str::to_owned).ok_or_else(
+            || rterr!("Failed to evaluate {} in Chromium", expression))
+    };
+    let html = evaluate(&mut browser, SERIALIZE_DOM)?;
+    let final_url = evaluate(&mut browser, "document.URL")?;
+
+    let pdf = if conf.pdf
+    {
+        let result = browser.call("Page.printToPDF", json!({"printBackground": true}),
+                                  session, Instant::now() + COMMAND_TIMEOUT)?;
+        Some(decodeData(&result, "PDF")?)
+    }
+    else
+    {
+        None
+    };
+    let screenshot = if conf.screenshot
+    {
+        let metrics = browser.call("Page.getLayoutMetrics", json!({}), session,
+                                   Instant::now() + COMMAND_TIMEOUT)?;
+        let size = if metrics["cssContentSize"].is_object()
+        {
+            &metrics["cssContentSize"]
+        }
+        else
+        {
+            &metrics["contentSize"]
+        };
+        let result = browser.call("Page.captureScreenshot", json!({
+            "format": "png",
+            "captureBeyondViewport": true,
+            "clip": {"x": 0, "y": 0, "width": size["width"], "height": size["height"],
+                     "scale": 1},
+        }), session, Instant::now() + COMMAND_TIMEOUT)?;
+        Some(decodeData(&result, "screenshot")?)
+    }
+    else
+    {
+        None
+    };
+    Ok(Rendering { url: final_url, html, pdf, screenshot })
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn launchOutput()
+    {
+        assert_eq!(devToolsUrl("DevTools listening on ws://127.0.0.1:41234/devtools/browser/abc\n"),
+                   Some("ws://127.0.0.1:41234/devtools/browser/abc"));
+        assert_eq!(devToolsUrl("[1234:5678:ERROR:gpu_init.cc(523)] Passthrough is not supported"),
+                   None);
+    }
+
+    #[test]
+    fn longLivedRequests()
+    {
+        let event = |t: &str| json!({"method": "Network.requestWillBeSent",
+                                     "params": {"requestId": "1", "type": t}});
+        assert!(isLongLived(&event("EventSource")));
+        assert!(isLongLived(&event("WebSocket")));
+        assert!(!isLongLived(&event("XHR")));
+        assert!(!isLongLived(&json!({"params": {}})));
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::path::{PathBuf, Path};
 
 use serde::Deserialize;
@@ -5,6 +6,7 @@ use serde::Deserialize;
 use crate::error::Error;
 
 #[derive(Deserialize, Clone)]
+#[allow(clippy::enum_variant_names)]
 pub enum TwitterAuth
 {
     GuestToken,
@@ -14,6 +16,11 @@ pub enum TwitterAuth
         access_token: String,
         access_token_secret: String,
     },
+    /// App-only authentication with OAuth 2 bearer token.
+    AppToken {
+        consumer_key: String,
+        consumer_secret: String,
+    },
 }
 
 impl Default for TwitterAuth
@@ -24,18 +31,149 @@ impl Default for TwitterAuth
     }
 }
 
+/// How to save a web page as a single HTML file.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum WebPageBackend
+{
+    /// Run the `monolith` program.
+    #[default]
+    Monolith,
+    /// Use the built-in inliner.
+    Native,
+    /// Render the page in Chromium.
+    Chromium,
+}
+
+/// Options of rendering web pages in Chromium.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct ChromiumConfig
+{
+    /// The Chromium executable. By default it is searched in `PATH`.
+    pub path: Option<String>,
+    /// Also save the page printed as PDF.
+    pub pdf: bool,
+    /// Also save a screenshot of the full page.
+    pub screenshot: bool,
+    /// How long to wait for a page to finish loading in seconds. The
+    /// page is saved as it is after this.
+    pub timeout: u64,
+}
+
+impl Default for ChromiumConfig
+{
+    fn default() -> Self
+    {
+        Self { path: None, pdf: false, screenshot: false, timeout: 60 }
+    }
+}
+
 #[derive(Deserialize, Clone)]
 pub struct SinglePageConfig
 {
+    #[serde(default)]
     pub download_font: bool,
-    pub disable_js: bool
+    #[serde(default)]
+    pub disable_js: bool,
+    #[serde(default)]
+    pub backend: WebPageBackend,
+    /// Also save the HTTP traffic as a WARC file. This uses the
+    /// built-in inliner instead of Monolith, and is not supported by
+    /// the Chromium backend.
+    #[serde(default)]
+    pub warc: bool,
+    /// Also extract the main article of the page as clean HTML and
+    /// Markdown, and take the title, authors and date from it.
+    #[serde(default)]
+    pub extract_article: bool,
+    #[serde(default)]
+    pub chromium: ChromiumConfig,
 }
 
 impl Default for SinglePageConfig
 {
     fn default() -> Self
     {
-        Self { download_font: false, disable_js: false }
+        Self { download_font: false, disable_js: false,
+               backend: WebPageBackend::default(), warc: false,
+               extract_article: false, chromium: ChromiumConfig::default() }
+    }
+}
+
+fn defaultExternalPriority() -> i32 { 20 }
+fn defaultExternalTimeout() -> u64 { 600 }
+
+/// An analyser that runs an external command. The command is invoked
+/// with `args`, followed by the URL and a scratch directory. It should
+/// print a JSON manifest of the resources to stdout.
+#[derive(Deserialize, Clone)]
+pub struct ExternalAnalyserConfig
+{
+    pub name: String,
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Host globs of the URLs this analyser handles.
+    pub hosts: Vec<String>,
+    /// Optional regex the URL path should match.
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default = "defaultExternalPriority")]
+    pub priority: i32,
+    /// Timeout of the command in seconds.
+    #[serde(default = "defaultExternalTimeout")]
+    pub timeout: u64,
+}
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct TwitterConfig
+{
+    /// Archive the whole thread by the author of a tweet, instead of
+    /// just the tweet.
+    pub thread: bool,
+    /// OAuth 2.0 user access token with the `bookmark.read`,
+    /// `tweet.read` and `users.read` scopes. The bookmarks API does
+    /// not accept the other kinds of authentication.
+    pub bookmarks_token: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct GitHubConfig
+{
+    /// Personal access token for the GitHub API. Without it the API
+    /// is limited to 60 requests per hour.
+    pub token: Option<String>,
+}
+
+/// Options of the video downloader (yt-dlp).
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct VideoConfig
+{
+    /// The yt-dlp executable.
+    pub program: String,
+    /// Format selection, passed to the “-f” option of yt-dlp.
+    pub format: String,
+    /// Subtitle languages, passed to the “--sub-langs” option of
+    /// yt-dlp.
+    pub subtitle_langs: String,
+    /// Whether to download automatically generated subtitles.
+    pub auto_subtitles: bool,
+}
+
+impl Default for VideoConfig
+{
+    fn default() -> Self
+    {
+        Self {
+            program: String::from("yt-dlp"),
+            format: String::from("bestvideo*+bestaudio/best"),
+            subtitle_langs: String::from("all"),
+            auto_subtitles: false,
+        }
     }
 }
 
@@ -46,7 +184,19 @@ pub struct Config
     #[serde(default)]
     pub twitter_auth: TwitterAuth,
     #[serde(default)]
+    pub twitter_config: TwitterConfig,
+    #[serde(default)]
     pub single_page_config: SinglePageConfig,
+    #[serde(default)]
+    pub video_config: VideoConfig,
+    #[serde(default)]
+    pub github_config: GitHubConfig,
+    /// Force a particular analyser for some hosts. The keys are host
+    /// globs, and the values are analyser names.
+    #[serde(default)]
+    pub analyser_overrides: BTreeMap<String, String>,
+    #[serde(default)]
+    pub external_analysers: Vec<ExternalAnalyserConfig>,
 }
 
 impl Config
@@ -67,7 +217,12 @@ impl Default for Config
         Self {
             root_dir: PathBuf::from("/"),
             twitter_auth: TwitterAuth::GuestToken,
+            twitter_config: TwitterConfig::default(),
             single_page_config: SinglePageConfig::default(),
+            video_config: VideoConfig::default(),
+            github_config: GitHubConfig::default(),
+            analyser_overrides: BTreeMap::new(),
+            external_analysers: Vec::new(),
         }
     }
 }
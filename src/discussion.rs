@@ -0,0 +1,402 @@
+use std::path::Path;
+
+use log::{info, warn, debug};
+use serde_json::Value;
+use url::Url;
+
+use crate::analyser;
+use crate::analyser::{Analysis, RecordInfo, ResourceAnalyser, TempItem, UrlRule};
+use crate::error::Error;
+use crate::html;
+
+/// How many Hacker News items to get at the same time.
+const HN_CONCURRENCY: usize = 16;
+
+/// The URLs handled by the discussion client.
+pub fn urlRules() -> Result<Vec<UrlRule>, Error>
+{
+    Ok(vec![
+        UrlRule::withPath("news.ycombinator.com", r"^/item$")?,
+        UrlRule::withPath("lobste.rs", r"^/s/[0-9a-z]+(/|$)")?,
+    ])
+}
+
+/// A discussion on a news aggregator.
+#[derive(PartialEq, Debug)]
+enum Site
+{
+    /// A Hacker News item, with its ID.
+    HackerNews(u64),
+    /// A Lobsters story, with its short ID.
+    Lobsters(String),
+}
+
+impl Site
+{
+    fn fromUrl(url: &str) -> Result<Self, Error>
+    {
+        let u = Url::parse(url).map_err(|_| rterr!("Invalid URL: {}", url))?;
+        match u.host_str()
+        {
+            Some("news.ycombinator.com") => u.query_pairs()
+                .find(|(key, _)| key == "id")
+                .and_then(|(_, id)| id.parse().ok())
+                .map(Self::HackerNews)
+                .ok_or_else(|| rterr!("Not a Hacker News item: {}", url)),
+            Some("lobste.rs") => u.path_segments()
+                .and_then(|mut s| if s.next() == Some("s") {s.next()} else {None})
+                .filter(|id| !id.is_empty())
+                .map(|id| Self::Lobsters(id.to_owned()))
+                .ok_or_else(|| rterr!("Not a Lobsters story: {}", url)),
+            _ => Err(rterr!("Unknown discussion site: {}", url)),
+        }
+    }
+}
+
+fn getJson(url: &str) -> Result<Value, Error>
+{
+    debug!("Getting {}...", url);
+    let res = ureq::get(url).call()
+        .map_err(|e| rterr!("Failed to get {}: {}", url, e))?;
+    let body = res.into_string().map_err(
+        |e| rterr!("Failed to read response from {}: {}", url, e))?;
+    serde_json::from_str(&body)
+        .map_err(|_| rterr!("Invalid JSON response from {}", url))
+}
+
+fn formatTime(stamp: Option<i64>) -> Option<String>
+{
+    let t = time::OffsetDateTime::from_unix_timestamp(stamp?).ok()?;
+    t.format(&time::format_description::well_known::Rfc3339).ok()
+}
+
+fn hnItem(id: u64) -> Result<Value, Error>
+{
+    getJson(&format!("https://hacker-news.firebaseio.com/v0/item/{}.json", id))
+}
+
+/// Get Hacker News item `id` with all its comments. The comments of
+/// an item are put in its “children”, in the order of “kids”.
+fn hnTree(id: u64) -> Result<Value, Error>
+{
+    let mut root = hnItem(id)?;
+    // Get the comments level by level, so that the items of a level
+    // can be fetched in parallel.
+    let mut level: Vec<Vec<usize>> = vec![Vec::new()];
+    loop
+    {
+        let mut ids: Vec<(Vec<usize>, u64)> = Vec::new();
+        for path in &level
+        {
+            let item = itemAt(&root, path);
+            for (i, kid) in item["kids"].as_array().into_iter().flatten().enumerate()
+            {
+                if let Some(kid) = kid.as_u64()
+                {
+                    let mut kid_path = path.clone();
+                    kid_path.push(i);
+                    ids.push((kid_path, kid));
+                }
+            }
+        }
+        if ids.is_empty()
+        {
+            break;
+        }
+        info!("Getting {} comments...", ids.len());
+        let mut items: Vec<Result<Value, Error>> = Vec::new();
+        for chunk in ids.chunks(HN_CONCURRENCY)
+        {
+            std::thread::scope(|s| {
+                let handles: Vec<_> = chunk.iter()
+                    .map(|(_, id)| s.spawn(move || hnItem(*id))).collect();
+                for h in handles
+                {
+                    items.push(h.join().unwrap_or_else(
+                        |_| Err(rterr!("Failed to get Hacker News item"))));
+                }
+            });
+        }
+        level.clear();
+        for ((path, _), item) in ids.into_iter().zip(items)
+        {
+            let (parent_path, index) = path.split_at(path.len() - 1);
+            let parent = itemAtMut(&mut root, parent_path);
+            if !parent["children"].is_array()
+            {
+                let count = parent["kids"].as_array().map(|k| k.len()).unwrap_or(0);
+                parent["children"] = Value::Array(vec![Value::Null; count]);
+            }
+            match item
+            {
+                Ok(item) =>
+                {
+                    parent["children"][index[0]] = item;
+                    level.push(path);
+                },
+                Err(e) => warn!("Failed to get comment: {}", e),
+            }
+        }
+    }
+    Ok(root)
+}
+
+fn itemAt<'a>(root: &'a Value, path: &[usize]) -> &'a Value
+{
+    path.iter().fold(root, |item, i| &item["children"][*i])
+}
+
+fn itemAtMut<'a>(root: &'a mut Value, path: &[usize]) -> &'a mut Value
+{
+    path.iter().fold(root, |item, i| &mut item["children"][*i])
+}
+
+fn renderHnComments(item: &Value, out: &mut String)
+{
+    let children = match item["children"].as_array()
+    {
+        Some(c) if !c.is_empty() => c,
+        _ => return,
+    };
+    out.push_str("<ul class=\"comments\">\n");
+    for child in children
+    {
+        if !child.is_object() || child["deleted"].as_bool() == Some(true) ||
+            child["dead"].as_bool() == Some(true)
+        {
+            continue;
+        }
+        out.push_str(&format!(
+            "<li>\n<div class=\"meta\"><b>{}</b> · {}</div>\n<p>{}</p>\n",
+            html::escape(child["by"].as_str().unwrap_or("[unknown]")),
+            formatTime(child["time"].as_i64()).unwrap_or_default(),
+            child["text"].as_str().unwrap_or("")));
+        renderHnComments(child, out);
+        out.push_str("</li>\n");
+    }
+    out.push_str("</ul>\n");
+}
+
+/// Render a discussion page with `title`, a metadata line `meta`, the
+/// URL of the article, the text of the post (HTML) and the rendered
+/// comments.
+fn renderThread(title: &str, meta: &str, url: Option<&str>, text: Option<&str>,
+                comments: &str) -> String
+{
+    let mut body = format!("<h1>{}</h1>\n<div class=\"meta\">{}</div>\n",
+                           html::escape(title), meta);
+    if let Some(u) = url
+    {
+        body.push_str(&format!("<p><a href=\"{0}\">{0}</a></p>\n", html::escape(u)));
+    }
+    if let Some(t) = text.filter(|t| !t.is_empty())
+    {
+        body.push_str(&format!("<div>{}</div>\n", t));
+    }
+    body.push_str("<h2>Comments</h2>\n");
+    body.push_str(comments);
+    html::page(title, &body)
+}
+
+/// The name of a Lobsters user, which is just the name in the JSON of
+/// a story, or an object in older versions.
+fn lobstersUser(user: &Value) -> &str
+{
+    user.as_str().or_else(|| user["username"].as_str()).unwrap_or("[unknown]")
+}
+
+/// Lobsters comments are a flat list in thread order, with their
+/// depths.
+fn renderLobstersComments(comments: &[Value]) -> String
+{
+    let mut out = String::new();
+    let mut depth = 0;
+    for comment in comments
+    {
+        let d = comment["depth"].as_u64().unwrap_or(0) + 1;
+        if d > depth
+        {
+            for _ in depth..d
+            {
+                out.push_str("<ul class=\"comments\">\n<li>\n");
+            }
+        }
+        else
+        {
+            for _ in d..depth
+            {
+                out.push_str("</li>\n</ul>\n");
+            }
+            out.push_str("</li>\n<li>\n");
+        }
+        depth = d;
+        out.push_str(&format!(
+            "<div class=\"meta\"><b>{}</b> · {} points · {}</div>\n{}\n",
+            html::escape(lobstersUser(&comment["commenting_user"])),
+            comment["score"].as_i64().unwrap_or(0),
+            html::escape(comment["created_at"].as_str().unwrap_or("")),
+            comment["comment"].as_str().unwrap_or("")));
+    }
+    for _ in 0..depth
+    {
+        out.push_str("</li>\n</ul>\n");
+    }
+    out
+}
+
+/// The discussion of a story, with the URL of the article it links to.
+struct Discussion
+{
+    info: RecordInfo,
+    article_url: Option<String>,
+    page: String,
+    raw: Value,
+}
+
+fn hackerNews(id: u64) -> Result<Discussion, Error>
+{
+    let story = hnTree(id)?;
+    let title = story["title"].as_str().unwrap_or("").to_owned();
+    let author = story["by"].as_str().unwrap_or("[unknown]");
+    let published = formatTime(story["time"].as_i64());
+    let mut comments = String::new();
+    renderHnComments(&story, &mut comments);
+    let meta = format!("Hacker News · by {} · {} points · {}",
+                       html::escape(author), story["score"].as_i64().unwrap_or(0),
+                       published.as_deref().unwrap_or(""));
+    let article_url = story["url"].as_str().map(|s| s.to_owned());
+    Ok(Discussion {
+        page: renderThread(&title, &meta, article_url.as_deref(),
+                           story["text"].as_str(), &comments),
+        info: RecordInfo {
+            title: Some(title),
+            authors: vec![author.to_owned()],
+            published,
+        },
+        article_url,
+        raw: story,
+    })
+}
+
+fn lobsters(id: &str) -> Result<Discussion, Error>
+{
+    let story = getJson(&format!("https://lobste.rs/s/{}.json", id))?;
+    let title = story["title"].as_str().unwrap_or("").to_owned();
+    let author = lobstersUser(&story["submitter_user"]).to_owned();
+    let published = story["created_at"].as_str().map(|s| s.to_owned());
+    let comments = renderLobstersComments(
+        story["comments"].as_array().map(|c| c.as_slice()).unwrap_or(&[]));
+    let meta = format!("Lobsters · by {} · {} points · {}",
+                       html::escape(&author), story["score"].as_i64().unwrap_or(0),
+                       html::escape(published.as_deref().unwrap_or("")));
+    let article_url = story["url"].as_str().filter(|u| !u.is_empty())
+        .map(|s| s.to_owned());
+    Ok(Discussion {
+        page: renderThread(&title, &meta, article_url.as_deref(),
+                           story["description"].as_str(), &comments),
+        info: RecordInfo {
+            title: Some(title),
+            authors: vec![author],
+            published,
+        },
+        article_url,
+        raw: story,
+    })
+}
+
+/// The client of news aggregators. The article linked by a story is
+/// archived by `article`, which is usually the web page downloader.
+pub struct Client
+{
+    article: Box<dyn ResourceAnalyser>,
+}
+
+impl Client
+{
+    pub fn new(article: Box<dyn ResourceAnalyser>) -> Self
+    {
+        Self { article }
+    }
+}
+
+impl analyser::ResourceAnalyser for Client
+{
+    fn analyse(&self, url: &str, scratch: &Path) -> Result<Analysis, Error>
+    {
+        let discussion = match Site::fromUrl(url)?
+        {
+            Site::HackerNews(id) => hackerNews(id)?,
+            Site::Lobsters(id) => lobsters(&id)?,
+        };
+
+        let mut analysis = Analysis::new();
+        analysis.info = discussion.info;
+        analysis.addWithRole(TempItem::Html(discussion.page), "discussion");
+        if let Some(article_url) = &discussion.article_url
+        {
+            info!("Archiving linked article at {}...", article_url);
+            // The comments are worth keeping even if the article is
+            // gone.
+            match self.article.analyse(article_url, scratch)
+            {
+                Ok(article) => for mut resource in article.resources
+                {
+                    if resource.info.role.is_none()
+                    {
+                        resource.info.role = Some(String::from("article"));
+                    }
+                    analysis.resources.push(resource);
+                },
+                Err(e) => warn!("Failed to archive article at {}: {}",
+                                article_url, e),
+            }
+        }
+        let raw = serde_json::to_string_pretty(&discussion.raw)
+            .map_err(|_| rterr!("Failed to serialize discussion"))?;
+        analysis.addWithRole(TempItem::Json(raw), "raw");
+        Ok(analysis)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn urls() -> Result<()>
+    {
+        assert_eq!(Site::fromUrl("https://news.ycombinator.com/item?id=8863")?,
+                   Site::HackerNews(8863));
+        assert_eq!(Site::fromUrl("https://lobste.rs/s/abc123/some_title")?,
+                   Site::Lobsters(String::from("abc123")));
+        assert!(Site::fromUrl("https://news.ycombinator.com/item").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn comments() -> Result<()>
+    {
+        let story: Value = serde_json::from_str(r#"{"children": [
+            {"by": "a", "text": "Top", "children": [{"by": "b<", "text": "Reply"}]},
+            {"deleted": true}]}"#)?;
+        let mut out = String::new();
+        renderHnComments(&story, &mut out);
+        assert!(out.contains("<b>b&lt;</b>"));
+        assert_eq!(out.matches("<li>").count(), 2);
+        assert_eq!(out.matches("<ul").count(), 2);
+
+        let comments: Vec<Value> = serde_json::from_str(r#"[
+            {"depth": 0, "comment": "<p>1</p>", "commenting_user": "a"},
+            {"depth": 1, "comment": "<p>1.1</p>", "commenting_user": {"username": "b"}},
+            {"depth": 2, "comment": "<p>1.1.1</p>", "commenting_user": "c"},
+            {"depth": 0, "comment": "<p>2</p>", "commenting_user": "d"}]"#)?;
+        let out = renderLobstersComments(&comments);
+        assert_eq!(out.matches("<ul").count(), out.matches("</ul>").count());
+        assert_eq!(out.matches("<li>").count(), 4);
+        assert_eq!(out.matches("<li>").count(), out.matches("</li>").count());
+        assert!(out.contains("<b>b</b>"));
+        Ok(())
+    }
+}
@@ -0,0 +1,218 @@
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{info, debug};
+use serde::Deserialize;
+
+use crate::analyser;
+use crate::analyser::{Analysis, TempItem, UrlRule};
+use crate::config::ExternalAnalyserConfig;
+use crate::error::Error;
+
+/// An item in the manifest printed by an external analyser. The
+/// manifest is a JSON array like
+///
+/// [{"file": "page.html"}, {"url": "https://some.url/a.jpg"},
+///  {"text": "Some text"}]
+///
+/// Relative file paths are relative to the scratch directory.
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ManifestItem
+{
+    File(PathBuf),
+    Url(String),
+    Text(String),
+}
+
+/// Files in the manifest must be in the scratch directory, because
+/// they are moved into the record.
+fn parseManifest(manifest: &str, scratch: &Path) -> Result<Vec<TempItem>, Error>
+{
+    let items: Vec<ManifestItem> = serde_json::from_str(manifest)
+        .map_err(|e| rterr!("Invalid analyser manifest: {}", e))?;
+    let scratch_dir = scratch.canonicalize().map_err(
+        |e| rterr!("Failed to resolve scratch directory {:?}: {}", scratch, e))?;
+    items.into_iter().map(|item| match item
+    {
+        ManifestItem::File(path) =>
+        {
+            let path = scratch.join(path);
+            if !path.is_file()
+            {
+                return Err(rterr!("File {:?} in analyser manifest does not exist",
+                                  path));
+            }
+            match path.canonicalize()
+            {
+                Ok(p) if p.starts_with(&scratch_dir) => Ok(TempItem::File(path)),
+                _ => Err(rterr!("File {:?} in analyser manifest is outside of \
+                                 the scratch directory", path)),
+            }
+        },
+        ManifestItem::Url(u) => Ok(TempItem::Url(u)),
+        ManifestItem::Text(t) => Ok(TempItem::Text(t)),
+    }).collect()
+}
+
+/// Wait for `child` to exit for at most `timeout`. Kill the child
+/// and return `None` if it times out.
+fn waitWithTimeout(child: &mut Child, timeout: Duration) ->
+    Result<Option<ExitStatus>, Error>
+{
+    let start = Instant::now();
+    loop
+    {
+        if let Some(status) = child.try_wait().map_err(
+            |e| rterr!("Failed to wait for analyser: {}", e))?
+        {
+            return Ok(Some(status));
+        }
+        if start.elapsed() >= timeout
+        {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// The URLs handled by the external analyser defined by `conf`.
+pub fn urlRules(conf: &ExternalAnalyserConfig) -> Result<Vec<UrlRule>, Error>
+{
+    conf.hosts.iter().map(|host| match &conf.path
+    {
+        Some(path) => UrlRule::withPath(host, path),
+        None => Ok(UrlRule::new(host)),
+    }).collect()
+}
+
+pub struct Plugin
+{
+    conf: ExternalAnalyserConfig,
+}
+
+impl Plugin
+{
+    pub fn new(conf: ExternalAnalyserConfig) -> Self
+    {
+        Self { conf }
+    }
+}
+
+impl analyser::ResourceAnalyser for Plugin
+{
+    fn analyse(&self, url: &str, scratch: &Path) -> Result<Analysis, Error>
+    {
+        let mut proc = Command::new(&self.conf.command);
+        proc.args(&self.conf.args).arg(url).arg(scratch)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        debug!("Running {:?}...", proc);
+        let mut child = proc.spawn().map_err(
+            |e| rterr!("Failed to run analyser {}: {}", self.conf.name, e))?;
+
+        // Read the outputs in separate threads, so that the command
+        // does not block on a full pipe.
+        let mut stdout = child.stdout.take().unwrap();
+        let stdout_reader = thread::spawn(move || {
+            let mut output = String::new();
+            stdout.read_to_string(&mut output).map(|_| output)
+        });
+        let stderr = child.stderr.take().unwrap();
+        let name = self.conf.name.clone();
+        let stderr_reader = thread::spawn(move || {
+            for line in BufReader::new(stderr).lines().map_while(Result::ok)
+            {
+                info!("[{}] {}", name, line);
+            }
+        });
+
+        let status = waitWithTimeout(
+            &mut child, Duration::from_secs(self.conf.timeout))?
+            .ok_or_else(|| rterr!("Analyser {} timed out after {} seconds",
+                                  self.conf.name, self.conf.timeout))?;
+        let _ = stderr_reader.join();
+        if !status.success()
+        {
+            return Err(match status.code()
+            {
+                Some(code) => rterr!("Analyser {} failed with code {}",
+                                     self.conf.name, code),
+                None => rterr!("Analyser {} was terminated by a signal",
+                               self.conf.name),
+            });
+        }
+        let output = stdout_reader.join()
+            .map_err(|_| rterr!("Failed to read output of analyser {}",
+                                self.conf.name))?
+            .map_err(|e| rterr!("Failed to read output of analyser {}: {}",
+                                self.conf.name, e))?;
+        parseManifest(&output, scratch).map(Analysis::from)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::analyser::ResourceAnalyser;
+    use anyhow::Result;
+
+    fn shellPlugin(script: &str, timeout: u64) -> Plugin
+    {
+        Plugin::new(ExternalAnalyserConfig {
+            name: String::from("test"),
+            command: String::from("sh"),
+            args: vec![String::from("-c"), script.to_owned(), String::from("sh")],
+            hosts: vec![String::from("*")],
+            path: None,
+            priority: 0,
+            timeout,
+        })
+    }
+
+    #[test]
+    fn analyse() -> Result<()>
+    {
+        let scratch = tempfile::tempdir()?;
+        let plugin = shellPlugin(
+            r#"echo "$1" > "$2/a.txt"; echo 'some log' >&2; echo '[{"file": "a.txt"}, {"text": "hi"}]'"#, 10);
+        let analysis = plugin.analyse("http://example.org/", scratch.path())?;
+        assert_eq!(analysis, Analysis::from(vec![
+            TempItem::File(scratch.path().join("a.txt")),
+            TempItem::Text(String::from("hi"))]));
+        assert_eq!(std::fs::read_to_string(scratch.path().join("a.txt"))?,
+                   "http://example.org/\n");
+        Ok(())
+    }
+
+    #[test]
+    fn failures() -> Result<()>
+    {
+        let scratch = tempfile::tempdir()?;
+        assert!(shellPlugin("exit 3", 10)
+                .analyse("http://example.org/", scratch.path()).is_err());
+        assert!(shellPlugin("sleep 5", 1)
+                .analyse("http://example.org/", scratch.path()).is_err());
+        assert!(shellPlugin(r#"echo '[{"file": "nonexistent"}]'"#, 10)
+                .analyse("http://example.org/", scratch.path()).is_err());
+
+        // Files outside of the scratch directory are not taken.
+        let inner = scratch.path().join("inner");
+        std::fs::create_dir(&inner)?;
+        let outside = scratch.path().join("secret");
+        std::fs::write(&outside, "secret")?;
+        assert!(parseManifest(&format!(r#"[{{"file": "{}"}}]"#, outside.display()), &inner)
+                .is_err());
+        assert!(parseManifest(r#"[{"file": "../secret"}]"#, &inner).is_err());
+        assert!(parseManifest(r#"[{"file": "inner/../secret"}]"#, scratch.path()).is_ok());
+        assert!(outside.exists());
+        Ok(())
+    }
+}
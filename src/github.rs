@@ -0,0 +1,370 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use log::{info, warn, debug};
+use serde_json::Value;
+use url::Url;
+
+use crate::analyser;
+use crate::analyser::{Analysis, RecordInfo, ResourceAnalyser, ResourceInfo,
+                      TempItem, UrlRule};
+use crate::config::GitHubConfig;
+use crate::error::Error;
+use crate::html;
+
+static API_BASE: &str = "https://api.github.com";
+static USER_AGENT: &str = "cain/0.1 (personal web archive)";
+/// First path segments on github.com that are not users.
+static RESERVED_OWNERS: &[&str] = &[
+    "about", "apps", "collections", "enterprise", "explore", "features",
+    "login", "marketplace", "notifications", "orgs", "pricing", "search",
+    "settings", "sponsors", "topics", "trending", "users"];
+
+/// The URLs handled by the GitHub client.
+pub fn urlRules() -> Result<Vec<UrlRule>, Error>
+{
+    Ok(vec![
+        UrlRule::withPath("github.com", r"^/[^/]+/[^/]+/?$")?,
+        UrlRule::withPath("github.com", r"^/[^/]+/[^/]+/(issues|pull)/\d+")?,
+        UrlRule::withPath("gist.github.com", r"^/([^/]+/)?[0-9a-f]+/?$")?,
+    ])
+}
+
+/// What a GitHub URL points to.
+#[derive(PartialEq, Debug)]
+enum Target
+{
+    Repo { owner: String, repo: String },
+    /// An issue or a pull request.
+    Issue { owner: String, repo: String, number: u64 },
+    Gist(String),
+}
+
+impl Target
+{
+    fn fromUrl(url: &str) -> Result<Self, Error>
+    {
+        let u = Url::parse(url).map_err(|_| rterr!("Invalid URL: {}", url))?;
+        let parts: Vec<&str> = u.path().trim_matches('/').split('/').collect();
+        let target = match (u.host_str(), &parts[..])
+        {
+            (Some("gist.github.com"), [.., id]) => Some(Self::Gist(id.to_string())),
+            (Some("github.com"), [owner, repo]) => Some(Self::Repo {
+                owner: owner.to_string(),
+                repo: repo.trim_end_matches(".git").to_owned(),
+            }),
+            (Some("github.com"), [owner, repo, "issues" | "pull", number, ..]) =>
+                number.parse().ok().map(|number| Self::Issue {
+                    owner: owner.to_string(), repo: repo.to_string(), number }),
+            _ => None,
+        };
+        match target
+        {
+            Some(Self::Repo { owner, .. }) | Some(Self::Issue { owner, .. })
+                if RESERVED_OWNERS.contains(&owner.as_str()) => None,
+            t => t,
+        }.ok_or_else(|| rterr!("Not a GitHub repository, issue or gist: {}", url))
+    }
+}
+
+fn formatConversation(issue: &Value, comments: &[Value]) -> String
+{
+    let title = format!("{} #{}", issue["title"].as_str().unwrap_or(""),
+                        issue["number"].as_u64().unwrap_or(0));
+    let kind = if issue["pull_request"].is_object() {"Pull request"} else {"Issue"};
+    let mut body = format!(
+        "<h1>{}</h1>\n<div class=\"meta\">{} · {} · opened by <b>{}</b> · {}</div>\n{}\n",
+        html::escape(&title), kind,
+        html::escape(issue["state"].as_str().unwrap_or("")),
+        html::escape(issue["user"]["login"].as_str().unwrap_or("[unknown]")),
+        html::escape(issue["created_at"].as_str().unwrap_or("")),
+        issue["body_html"].as_str().unwrap_or(""));
+    body.push_str("<h2>Comments</h2>\n<ul class=\"comments\">\n");
+    for comment in comments
+    {
+        body.push_str(&format!(
+            "<li>\n<div class=\"meta\"><b>{}</b> · {}</div>\n{}\n</li>\n",
+            html::escape(comment["user"]["login"].as_str().unwrap_or("[unknown]")),
+            html::escape(comment["created_at"].as_str().unwrap_or("")),
+            comment["body_html"].as_str().unwrap_or("")));
+    }
+    body.push_str("</ul>\n");
+    html::page(&title, &body)
+}
+
+/// The files in `gist`, as their names, their content if it is not
+/// truncated, and their raw URLs.
+fn gistFiles(gist: &Value) -> Vec<(String, Option<String>, Option<String>)>
+{
+    gist["files"].as_object().into_iter().flatten().map(|(name, file)| {
+        let content = if file["truncated"].as_bool() == Some(true)
+        {
+            None
+        }
+        else
+        {
+            file["content"].as_str().map(|s| s.to_owned())
+        };
+        (name.clone(), content, file["raw_url"].as_str().map(|s| s.to_owned()))
+    }).collect()
+}
+
+/// Turn `name` into a safe filename.
+fn safeFilename(name: &str) -> String
+{
+    let name: String = name.chars()
+        .map(|c| if c == '/' || c == '\\' || c.is_control() {'_'} else {c})
+        .collect();
+    if name.starts_with('.') {format!("_{}", name)} else {name}
+}
+
+pub struct Client
+{
+    conf: GitHubConfig,
+    fallback: Box<dyn ResourceAnalyser>,
+}
+
+impl Client
+{
+    /// Create a client. URLs that are not repositories, issues or
+    /// gists are handled by `fallback`.
+    pub fn new(conf: GitHubConfig, fallback: Box<dyn ResourceAnalyser>) -> Self
+    {
+        Self { conf, fallback }
+    }
+
+    fn get(&self, url: &str, accept: &str) -> Result<ureq::Response, Error>
+    {
+        debug!("Getting {}...", url);
+        let mut req = ureq::get(url).set("User-Agent", USER_AGENT)
+            .set("Accept", accept);
+        if let Some(token) = &self.conf.token
+        {
+            req = req.set("Authorization", &format!("Bearer {}", token));
+        }
+        req.call().map_err(|e| rterr!("Failed to get {}: {}", url, e))
+    }
+
+    fn getJson(&self, url: &str) -> Result<Value, Error>
+    {
+        let body = self.get(url, "application/vnd.github.full+json")?
+            .into_string().map_err(
+                |e| rterr!("Failed to read response from {}: {}", url, e))?;
+        serde_json::from_str(&body)
+            .map_err(|_| rterr!("Invalid JSON response from {}", url))
+    }
+
+    /// Download `url` into file `target`.
+    fn download(&self, url: &str, target: &Path) -> Result<(), Error>
+    {
+        let res = self.get(url, "*/*")?;
+        let mut f = std::fs::File::create(target).map_err(
+            |e| rterr!("Failed to create {:?}: {}", target, e))?;
+        std::io::copy(&mut res.into_reader().take(10_000_000_000), &mut f)
+            .map_err(|e| rterr!("Failed to download {}: {}", url, e))?;
+        Ok(())
+    }
+
+    fn analyseRepo(&self, owner: &str, repo: &str, scratch: &Path) ->
+        Result<Analysis, Error>
+    {
+        let data = self.getJson(&format!("{}/repos/{}/{}", API_BASE, owner, repo))?;
+        let name = data["full_name"].as_str().unwrap_or(repo).to_owned();
+        let branch = data["default_branch"].as_str()
+            .ok_or_else(|| rterr!("Failed to get default branch of {}", name))?;
+
+        let mut analysis = Analysis::new();
+        analysis.info = RecordInfo {
+            title: Some(name.clone()),
+            authors: data["owner"]["login"].as_str()
+                .map(|o| vec![o.to_owned()]).unwrap_or_default(),
+            published: data["created_at"].as_str().map(|s| s.to_owned()),
+        };
+
+        match self.get(&format!("{}/repos/{}/readme", API_BASE, name),
+                       "application/vnd.github.html")
+        {
+            Ok(res) =>
+            {
+                let readme = res.into_string().map_err(
+                    |e| rterr!("Failed to read README of {}: {}", name, e))?;
+                analysis.addWithRole(TempItem::Html(html::page(&name, &readme)),
+                                     "readme");
+            },
+            Err(e) => warn!("Failed to get README: {}", e),
+        }
+
+        let tarball_url = format!("{}/repos/{}/tarball/{}", API_BASE, name, branch);
+        info!("Downloading source of {} at branch {}...", name, branch);
+        let tarball_name = safeFilename(&format!("{}-{}.tar.gz", repo, branch));
+        let tarball: PathBuf = scratch.join(&tarball_name);
+        self.download(&tarball_url, &tarball)?;
+        analysis.addWithInfo(TempItem::File(tarball), ResourceInfo {
+            role: Some(String::from("source")),
+            url: Some(tarball_url),
+            content_type: Some(String::from("application/gzip")),
+            original_name: Some(tarball_name),
+            ..ResourceInfo::default()
+        });
+
+        let raw = serde_json::to_string_pretty(&data)
+            .map_err(|_| rterr!("Failed to serialize repository"))?;
+        analysis.addWithRole(TempItem::Json(raw), "raw");
+        Ok(analysis)
+    }
+
+    fn analyseIssue(&self, owner: &str, repo: &str, number: u64) ->
+        Result<Analysis, Error>
+    {
+        let issue_url = format!("{}/repos/{}/{}/issues/{}", API_BASE, owner, repo,
+                                number);
+        let issue = self.getJson(&issue_url)?;
+        let mut comments: Vec<Value> = Vec::new();
+        for page in 1..
+        {
+            let data = self.getJson(&format!("{}/comments?per_page=100&page={}",
+                                             issue_url, page))?;
+            let batch = data.as_array().cloned().unwrap_or_default();
+            let done = batch.len() < 100;
+            comments.extend(batch);
+            if done
+            {
+                break;
+            }
+        }
+        info!("Found {} comments.", comments.len());
+
+        let mut analysis = Analysis::new();
+        analysis.info = RecordInfo {
+            title: issue["title"].as_str().map(|s| s.to_owned()),
+            authors: issue["user"]["login"].as_str()
+                .map(|u| vec![u.to_owned()]).unwrap_or_default(),
+            published: issue["created_at"].as_str().map(|s| s.to_owned()),
+        };
+        analysis.addWithRole(TempItem::Html(formatConversation(&issue, &comments)),
+                             "conversation");
+        let raw = serde_json::to_string_pretty(&serde_json::json!({
+            "issue": issue,
+            "comments": comments,
+        })).map_err(|_| rterr!("Failed to serialize conversation"))?;
+        analysis.addWithRole(TempItem::Json(raw), "raw");
+        Ok(analysis)
+    }
+
+    fn analyseGist(&self, id: &str, scratch: &Path) -> Result<Analysis, Error>
+    {
+        let gist = self.getJson(&format!("{}/gists/{}", API_BASE, id))?;
+        let mut analysis = Analysis::new();
+        analysis.info = RecordInfo {
+            title: gist["description"].as_str().filter(|s| !s.is_empty())
+                .map(|s| s.to_owned()),
+            authors: gist["owner"]["login"].as_str()
+                .map(|u| vec![u.to_owned()]).unwrap_or_default(),
+            published: gist["created_at"].as_str().map(|s| s.to_owned()),
+        };
+        let dir = scratch.join("gist");
+        std::fs::create_dir_all(&dir).map_err(
+            |e| rterr!("Failed to create directory at {:?}: {}", dir, e))?;
+        for (name, content, raw_url) in gistFiles(&gist)
+        {
+            let target = dir.join(safeFilename(&name));
+            match (content, &raw_url)
+            {
+                (Some(c), _) => std::fs::write(&target, c).map_err(
+                    |e| rterr!("Failed to write {:?}: {}", target, e))?,
+                (None, Some(u)) => self.download(u, &target)?,
+                (None, None) =>
+                {
+                    warn!("Gist file {} has no content.", name);
+                    continue;
+                },
+            }
+            info!("Found gist file {}.", name);
+            analysis.addWithInfo(TempItem::File(target), ResourceInfo {
+                role: Some(String::from("file")),
+                url: raw_url,
+                original_name: Some(name),
+                ..ResourceInfo::default()
+            });
+        }
+        let raw = serde_json::to_string_pretty(&gist)
+            .map_err(|_| rterr!("Failed to serialize gist"))?;
+        analysis.addWithRole(TempItem::Json(raw), "raw");
+        Ok(analysis)
+    }
+}
+
+impl analyser::ResourceAnalyser for Client
+{
+    fn analyse(&self, url: &str, scratch: &Path) -> Result<Analysis, Error>
+    {
+        match Target::fromUrl(url)
+        {
+            Ok(Target::Repo { owner, repo }) => self.analyseRepo(&owner, &repo, scratch),
+            Ok(Target::Issue { owner, repo, number }) =>
+                self.analyseIssue(&owner, &repo, number),
+            Ok(Target::Gist(id)) => self.analyseGist(&id, scratch),
+            Err(e) =>
+            {
+                info!("{}. Archiving as a web page...", e);
+                self.fallback.analyse(url, scratch)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn urls() -> Result<()>
+    {
+        assert_eq!(Target::fromUrl("https://github.com/MetroWind/cain")?,
+                   Target::Repo { owner: String::from("MetroWind"),
+                                  repo: String::from("cain") });
+        assert_eq!(Target::fromUrl("https://github.com/rust-lang/rust/pull/1234/files")?,
+                   Target::Issue { owner: String::from("rust-lang"),
+                                   repo: String::from("rust"), number: 1234 });
+        assert_eq!(Target::fromUrl("https://gist.github.com/someone/0123abcd")?,
+                   Target::Gist(String::from("0123abcd")));
+        assert!(Target::fromUrl("https://github.com/topics/rust").is_err());
+        assert!(Target::fromUrl("https://github.com/MetroWind").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn conversation() -> Result<()>
+    {
+        let issue: Value = serde_json::from_str(r#"{
+            "title": "Crash <on> start", "number": 7, "state": "open",
+            "user": {"login": "a"}, "body_html": "<p>It crashes.</p>",
+            "pull_request": {}}"#)?;
+        let comments: Vec<Value> = serde_json::from_str(r#"[
+            {"user": {"login": "b"}, "body_html": "<p>Same here.</p>"}]"#)?;
+        let page = formatConversation(&issue, &comments);
+        assert!(page.contains("<h1>Crash &lt;on&gt; start #7</h1>"));
+        assert!(page.contains("Pull request"));
+        assert!(page.contains("<p>Same here.</p>"));
+        Ok(())
+    }
+
+    #[test]
+    fn gist() -> Result<()>
+    {
+        let gist: Value = serde_json::from_str(r#"{"files": {
+            "a.rs": {"content": "fn main() {}", "truncated": false,
+                     "raw_url": "https://gist.githubusercontent.com/a.rs"},
+            "big.txt": {"content": "...", "truncated": true,
+                        "raw_url": "https://gist.githubusercontent.com/big.txt"}}}"#)?;
+        let files = gistFiles(&gist);
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].1.as_deref(), Some("fn main() {}"));
+        assert_eq!(files[1].1, None);
+        assert_eq!(safeFilename("../x"), "_.._x");
+        assert_eq!(safeFilename("cain-release/1.x.tar.gz"), "cain-release_1.x.tar.gz");
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+/// Escape the special characters in `s` for HTML text and attribute
+/// values.
+pub fn escape(s: &str) -> String
+{
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars()
+    {
+        match c
+        {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&#39;"),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+/// Decode the character references in `s`. Only the numeric ones and
+/// the few named ones that are common in attribute values are
+/// supported.
+pub fn unescape(s: &str) -> String
+{
+    let mut result = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('&')
+    {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.find(';')
+        {
+            Some(end) if end <= 10 => end,
+            _ =>
+            {
+                result.push('&');
+                rest = &rest[1..];
+                continue;
+            },
+        };
+        let name = &rest[1..end];
+        let c = match name
+        {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some('\u{a0}'),
+            _ if name.starts_with("#x") || name.starts_with("#X") =>
+                u32::from_str_radix(&name[2..], 16).ok().and_then(char::from_u32),
+            _ if name.starts_with('#') =>
+                name[1..].parse::<u32>().ok().and_then(char::from_u32),
+            _ => None,
+        };
+        match c
+        {
+            Some(c) =>
+            {
+                result.push(c);
+                rest = &rest[end + 1..];
+            },
+            None =>
+            {
+                result.push('&');
+                rest = &rest[1..];
+            },
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+static STYLE: &str = r#"
+body { max-width: 45em; margin: 2em auto; padding: 0 1em;
+       font-family: sans-serif; line-height: 1.5; }
+img, video { max-width: 100%; }
+.meta { color: #666; font-size: 0.9em; }
+img.avatar { width: 48px; height: 48px; border-radius: 50%;
+             vertical-align: middle; }
+blockquote { border-left: 3px solid #ddd; margin-left: 0;
+             padding-left: 1em; }
+ul.comments { list-style: none; padding-left: 1.2em;
+              border-left: 1px solid #ddd; }
+"#;
+
+/// Make a standalone HTML page with `body` as the content of the body
+/// element. `body` is not escaped.
+pub fn page(title: &str, body: &str) -> String
+{
+    format!(r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{}</title>
+<style>{}</style>
+</head>
+<body>
+{}
+</body>
+</html>
+"#, escape(title), STYLE, body)
+}
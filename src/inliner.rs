@@ -0,0 +1,690 @@
+//! A built-in replacement of Monolith. It saves a web page as a single
+//! HTML file, with the style sheets, images and (optionally) scripts
+//! and fonts inlined as data URLs.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::io::Read;
+
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
+use log::{debug, warn};
+use lol_html::html_content::ContentType;
+use lol_html::{element, text, rewrite_str, RewriteStrSettings};
+use regex::{Captures, Regex};
+use url::Url;
+
+use crate::error::Error;
+use crate::html;
+use crate::warc;
+
+/// Resources larger than this are left as links.
+const MAX_RESOURCE_SIZE: u64 = 32 * 1024 * 1024;
+/// The maximal depth of nested `@import`s in style sheets.
+const MAX_IMPORT_DEPTH: u32 = 5;
+const MAX_REDIRECTS: u32 = 10;
+
+/// The headers of every request. The content is requested without
+/// compression, so that it is recorded as is in WARC files.
+static REQUEST_HEADERS: &[(&str, &str)] = &[
+    ("User-Agent", concat!("cain/", env!("CARGO_PKG_VERSION"))),
+    ("Accept", "*/*"),
+    ("Accept-Encoding", "identity"),
+];
+
+/// Like Monolith’s `--isolate`, stop the saved page from accessing
+/// the network.
+static ISOLATION: &str = r#"<meta charset="utf-8"><meta http-equiv="Content-Security-Policy" content="default-src 'unsafe-eval' 'unsafe-inline' data:;">"#;
+
+/// The link types of `<link>` elements that only make the browser
+/// fetch something early, which is pointless for a saved page.
+static HINT_LINKS: &[&str] = &["preload", "prefetch", "modulepreload",
+                               "dns-prefetch", "preconnect", "prerender"];
+
+/// Guess the content type of a resource without a Content-Type
+/// header.
+fn guessContentType(url: &Url) -> &'static str
+{
+    let ext = url.path().rsplit('.').next().unwrap_or("").to_lowercase();
+    match ext.as_str()
+    {
+        "css" => "text/css",
+        "js" | "mjs" => "text/javascript",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "svg" => "image/svg+xml",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        _ => "application/octet-stream",
+    }
+}
+
+fn dataUrl(content_type: &str, data: &[u8]) -> String
+{
+    format!("data:{};base64,{}", content_type, base64::encode(data))
+}
+
+/// Get the encoding in the charset parameter of the Content-Type
+/// header `value`.
+fn charsetOf(value: &str) -> Option<&'static Encoding>
+{
+    value.split(';').skip(1).filter_map(|p| p.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+        .and_then(|(_, label)| Encoding::for_label(label.trim().trim_matches('"').as_bytes()))
+}
+
+/// Decode the HTML document `data`. Like browsers, the encoding is
+/// taken from the byte order mark, the Content-Type `charset`, or the
+/// `<meta>` at the start of the document, in this order.
+fn decodeHtml(data: &[u8], charset: Option<&'static Encoding>) -> String
+{
+    let meta = || {
+        let head = &data[..data.len().min(1024)];
+        let re = regex::bytes::Regex::new(
+            r#"(?i-u)<meta[^>]+charset\s*=\s*["']?\s*([a-z0-9_:.-]+)"#).unwrap();
+        re.captures(head).and_then(|caps| Encoding::for_label(&caps[1]))
+            // A document that could be read as ASCII is not UTF-16.
+            .map(|e| e.output_encoding())
+    };
+    let encoding = charset.or_else(meta).unwrap_or_else(|| {
+        if std::str::from_utf8(data).is_ok() {UTF_8} else {WINDOWS_1252}
+    });
+    // This also handles the byte order mark.
+    encoding.decode(data).0.into_owned()
+}
+
+/// Decode the style sheet `data`. The encoding is taken from the byte
+/// order mark, the Content-Type `charset`, or the `@charset` rule.
+/// The `@charset` rule is removed, because the result is not in that
+/// encoding any more.
+fn decodeCss(data: &[u8], charset: Option<&'static Encoding>) -> String
+{
+    let rule = regex::bytes::Regex::new(r#"(?-u)^(?:\xEF\xBB\xBF)?@charset "([^"]*)";"#).unwrap();
+    let declared = rule.captures(data).and_then(|caps| Encoding::for_label(&caps[1]))
+        .map(|e| e.output_encoding());
+    let css = charset.or(declared).unwrap_or(UTF_8).decode(data).0;
+    match css.strip_prefix("@charset \"")
+    {
+        Some(rest) => rest.split_once("\";").map(|(_, css)| css.to_owned())
+            .unwrap_or_else(|| css.to_string()),
+        None => css.into_owned(),
+    }
+}
+
+/// Get the links of the `<link>` element `rel` as a list of lowercase
+/// link types.
+fn linkTypes(rel: &str) -> Vec<String>
+{
+    rel.split_whitespace().map(|t| t.to_lowercase()).collect()
+}
+
+/// Remove all the `@font-face` rules from `css`.
+fn removeFonts(css: &str) -> String
+{
+    Regex::new(r"(?i)@font-face\s*\{[^}]*\}").unwrap().replace_all(css, "").into_owned()
+}
+
+/// A fetched resource.
+struct Fetched
+{
+    data: Vec<u8>,
+    /// The MIME type, without the parameters.
+    content_type: String,
+    /// The encoding in the Content-Type header.
+    charset: Option<&'static Encoding>,
+    /// The URL after redirection.
+    url: Url,
+}
+
+pub struct Inliner
+{
+    download_font: bool,
+    disable_js: bool,
+    /// Data URLs of the resources that have been fetched, keyed by
+    /// their URLs. `None` if the fetch failed.
+    cache: RefCell<HashMap<String, Option<String>>>,
+    agent: ureq::Agent,
+    warc: RefCell<Option<warc::Writer>>,
+}
+
+impl Inliner
+{
+    pub fn new(download_font: bool, disable_js: bool) -> Self
+    {
+        Self {
+            download_font, disable_js,
+            cache: RefCell::new(HashMap::new()),
+            agent: ureq::AgentBuilder::new().redirects(0).build(),
+            warc: RefCell::new(None),
+        }
+    }
+
+    /// Record the HTTP traffic into `writer` from now on.
+    pub fn recordWarc(&mut self, writer: warc::Writer)
+    {
+        self.warc = RefCell::new(Some(writer));
+    }
+
+    /// Stop recording the HTTP traffic, and finish the WARC file if
+    /// there is one. Return whether there was one.
+    pub fn finishWarc(&self) -> Result<bool, Error>
+    {
+        match self.warc.borrow_mut().take()
+        {
+            Some(writer) => writer.finish().map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Fetch `url`.
+    fn fetch(&self, url: &Url) -> Result<Fetched, Error>
+    {
+        let mut url = url.clone();
+        // Redirections are followed here instead of by ureq, so that
+        // every response can be recorded.
+        for _ in 0..MAX_REDIRECTS
+        {
+            if url.scheme() != "http" && url.scheme() != "https"
+            {
+                return Err(rterr!("Unsupported URL: {}", url));
+            }
+            debug!("Getting {}...", url);
+            let mut req = self.agent.get(url.as_str());
+            for (name, value) in REQUEST_HEADERS
+            {
+                req = req.set(name, value);
+            }
+            let res = match req.call()
+            {
+                Ok(res) => res,
+                Err(ureq::Error::Status(_, res)) => res,
+                Err(e) => return Err(rterr!("Failed to get {}: {}", url, e)),
+            };
+            let status = res.status();
+            let location = res.header("Location").map(str::to_owned);
+            let content_type = match res.header("Content-Type")
+            {
+                Some(t) if !t.is_empty() =>
+                    t.split(';').next().unwrap().trim().to_lowercase(),
+                _ => guessContentType(&url).to_owned(),
+            };
+            let charset = res.header("Content-Type").and_then(charsetOf);
+            // The response is consumed by reading the body, so the
+            // head is saved first.
+            let head = self.warc.borrow().as_ref().map(|_| warc::responseHead(&res));
+            let mut data = Vec::new();
+            res.into_reader().take(MAX_RESOURCE_SIZE + 1).read_to_end(&mut data)
+                .map_err(|e| rterr!("Failed to read {}: {}", url, e))?;
+            let truncated = data.len() as u64 > MAX_RESOURCE_SIZE;
+            if let (Some(head), Some(writer)) = (head, self.warc.borrow_mut().as_mut())
+            {
+                let body = &data[..data.len().min(MAX_RESOURCE_SIZE as usize)];
+                writer.writeExchange(&url, &warc::requestHead(&url, REQUEST_HEADERS),
+                                     &head, body, truncated)?;
+            }
+
+            if (300..400).contains(&status)
+            {
+                if let Some(target) = location.and_then(|l| url.join(&l).ok())
+                {
+                    url = target;
+                    continue;
+                }
+            }
+            if status >= 400
+            {
+                return Err(rterr!("Failed to get {}: status code {}", url, status));
+            }
+            if truncated
+            {
+                return Err(rterr!("{} is too large to inline", url));
+            }
+            return Ok(Fetched { data, content_type, charset, url });
+        }
+        Err(rterr!("Too many redirections from {}", url))
+    }
+
+    /// Fetch `url` as a data URL. Failures are logged, and result in
+    /// `None`.
+    fn dataUrlOf(&self, url: &Url) -> Option<String>
+    {
+        if let Some(cached) = self.cache.borrow().get(url.as_str())
+        {
+            return cached.clone();
+        }
+        let result = match self.fetch(url)
+        {
+            Ok(res) =>
+            {
+                if res.content_type == "text/css"
+                {
+                    let css = self.inlineCss(&decodeCss(&res.data, res.charset), &res.url, 1);
+                    Some(dataUrl(&res.content_type, css.as_bytes()))
+                }
+                else
+                {
+                    Some(dataUrl(&res.content_type, &res.data))
+                }
+            },
+            Err(e) =>
+            {
+                warn!("{}", e);
+                None
+            },
+        };
+        self.cache.borrow_mut().insert(url.as_str().to_owned(), result.clone());
+        result
+    }
+
+    /// Inline the resource at `link` relative to `base`. If it cannot
+    /// be inlined, the absolute URL is returned instead.
+    fn inlineUrl(&self, link: &str, base: &Url) -> String
+    {
+        let link = link.trim();
+        if link.is_empty() || link.starts_with('#') || link.starts_with("data:")
+        {
+            return link.to_owned();
+        }
+        match base.join(link)
+        {
+            Ok(u) => self.dataUrlOf(&u).unwrap_or_else(|| u.to_string()),
+            Err(_) => link.to_owned(),
+        }
+    }
+
+    /// Inline every image candidate in a `srcset` attribute.
+    fn inlineSrcset(&self, srcset: &str, base: &Url) -> String
+    {
+        // Data URLs contain commas, and it is not worth parsing them
+        // properly.
+        if srcset.contains("data:")
+        {
+            return srcset.to_owned();
+        }
+        srcset.split(',').filter_map(|candidate| {
+            let mut parts = candidate.split_whitespace();
+            let link = parts.next()?;
+            let descriptors: Vec<&str> = parts.collect();
+            let mut result = self.inlineUrl(link, base);
+            for d in descriptors
+            {
+                result.push(' ');
+                result.push_str(d);
+            }
+            Some(result)
+        }).collect::<Vec<String>>().join(", ")
+    }
+
+    /// Inline the `@import`s and `url()`s in the style sheet `css`,
+    /// whose URL is `base`.
+    fn inlineCss(&self, css: &str, base: &Url, depth: u32) -> String
+    {
+        let css = if self.download_font {css.to_owned()} else {removeFonts(css)};
+        let import = Regex::new(
+            r#"(?i)@import\s+(?:url\(\s*)?(?:"([^"]*)"|'([^']*)'|([^)\s;]+))\s*\)?([^;]*);"#)
+            .unwrap();
+        let css = import.replace_all(&css, |caps: &Captures| {
+            let link = caps.get(1).or(caps.get(2)).or(caps.get(3)).unwrap().as_str();
+            let media = caps[4].trim();
+            let target = match base.join(link)
+            {
+                Ok(u) => u,
+                Err(_) => return caps[0].to_owned(),
+            };
+            if depth >= MAX_IMPORT_DEPTH
+            {
+                return format!("@import url(\"{}\") {};", target, media);
+            }
+            match self.fetch(&target)
+            {
+                Ok(res) =>
+                {
+                    let imported = self.inlineCss(&decodeCss(&res.data, res.charset),
+                                                  &res.url, depth + 1);
+                    format!("@import url(\"{}\") {};", dataUrl("text/css", imported.as_bytes()),
+                            media)
+                },
+                Err(e) =>
+                {
+                    warn!("{}", e);
+                    format!("@import url(\"{}\") {};", target, media)
+                },
+            }
+        });
+        let url = Regex::new(r#"(?i)\burl\(\s*(?:"([^"]*)"|'([^']*)'|([^)\s]*))\s*\)"#).unwrap();
+        url.replace_all(&css, |caps: &Captures| {
+            let link = caps.get(1).or(caps.get(2)).or(caps.get(3)).unwrap().as_str();
+            format!("url(\"{}\")", self.inlineUrl(link, base))
+        }).into_owned()
+    }
+
+    /// Inline the resources in the HTML document `doc`, whose URL is
+    /// `url`. The result is declared as UTF-8, whatever the encoding of
+    /// the original document was.
+    pub fn inlineHtml(&self, doc: &str, url: &Url) -> Result<String, Error>
+    {
+        // The base URL changes when there is a <base> element.
+        let base = RefCell::new(url.clone());
+        let style = RefCell::new(String::new());
+        let disable_js = self.disable_js;
+        let has_head = Cell::new(false);
+        let settings = RewriteStrSettings {
+            element_content_handlers: vec![
+                element!("head", |el| {
+                    el.prepend(ISOLATION, ContentType::Html);
+                    has_head.set(true);
+                    Ok(())
+                }),
+                element!("meta[charset], meta[http-equiv]", |el| {
+                    let equiv = el.get_attribute("http-equiv").unwrap_or_default()
+                        .to_lowercase();
+                    if el.has_attribute("charset") || equiv == "content-type" ||
+                        equiv == "content-security-policy" || equiv == "refresh"
+                    {
+                        el.remove();
+                    }
+                    Ok(())
+                }),
+                element!("base[href]", |el| {
+                    let href = html::unescape(&el.get_attribute("href").unwrap());
+                    if let Ok(u) = url.join(&href)
+                    {
+                        *base.borrow_mut() = u;
+                    }
+                    el.remove();
+                    Ok(())
+                }),
+                element!("link[href]", |el| {
+                    let href = html::unescape(&el.get_attribute("href").unwrap());
+                    let types = linkTypes(&el.get_attribute("rel").unwrap_or_default());
+                    let base = base.borrow();
+                    if types.iter().any(|t| HINT_LINKS.contains(&t.as_str()))
+                    {
+                        el.remove();
+                        return Ok(());
+                    }
+                    let target = match base.join(&href)
+                    {
+                        Ok(u) => u,
+                        Err(_) => return Ok(()),
+                    };
+                    if types.iter().any(|t| t == "stylesheet")
+                    {
+                        match self.fetch(&target)
+                        {
+                            Ok(res) =>
+                            {
+                                let css = self.inlineCss(&decodeCss(&res.data, res.charset),
+                                                         &res.url, 1);
+                                let media = el.get_attribute("media").map(
+                                    |m| format!(" media=\"{}\"", m)).unwrap_or_default();
+                                el.replace(&format!("<style{}>{}</style>", media,
+                                                    css.replace("</style", "<\\/style")),
+                                           ContentType::Html);
+                            },
+                            Err(e) =>
+                            {
+                                warn!("{}", e);
+                                el.set_attribute("href", target.as_str())?;
+                            },
+                        }
+                    }
+                    else if types.iter().any(|t| t.contains("icon"))
+                    {
+                        el.set_attribute("href", &self.inlineUrl(&href, &base))?;
+                    }
+                    else
+                    {
+                        el.set_attribute("href", target.as_str())?;
+                    }
+                    Ok(())
+                }),
+                element!("img[src], input[type=image][src]", |el| {
+                    let src = html::unescape(&el.get_attribute("src").unwrap());
+                    el.set_attribute("src", &self.inlineUrl(&src, &base.borrow()))?;
+                    Ok(())
+                }),
+                element!("img[srcset], source[srcset]", |el| {
+                    let srcset = html::unescape(&el.get_attribute("srcset").unwrap());
+                    el.set_attribute("srcset", &self.inlineSrcset(&srcset, &base.borrow()))?;
+                    Ok(())
+                }),
+                element!("video[poster]", |el| {
+                    let poster = html::unescape(&el.get_attribute("poster").unwrap());
+                    el.set_attribute("poster", &self.inlineUrl(&poster, &base.borrow()))?;
+                    Ok(())
+                }),
+                // Media and frames are too large or too complicated to
+                // inline, so just make sure they still point to the
+                // right place.
+                element!("a[href], area[href]", |el| {
+                    let href = html::unescape(&el.get_attribute("href").unwrap());
+                    if !href.starts_with('#')
+                    {
+                        if let Ok(u) = base.borrow().join(&href)
+                        {
+                            el.set_attribute("href", u.as_str())?;
+                        }
+                    }
+                    Ok(())
+                }),
+                element!("video[src], audio[src], source[src], iframe[src], embed[src]", |el| {
+                    let src = html::unescape(&el.get_attribute("src").unwrap());
+                    if let Ok(u) = base.borrow().join(&src)
+                    {
+                        el.set_attribute("src", u.as_str())?;
+                    }
+                    Ok(())
+                }),
+                element!("form[action]", |el| {
+                    let action = html::unescape(&el.get_attribute("action").unwrap());
+                    if let Ok(u) = base.borrow().join(&action)
+                    {
+                        el.set_attribute("action", u.as_str())?;
+                    }
+                    Ok(())
+                }),
+                element!("[style]", |el| {
+                    let css = html::unescape(&el.get_attribute("style").unwrap());
+                    el.set_attribute("style", &self.inlineCss(&css, &base.borrow(), 1))?;
+                    Ok(())
+                }),
+                text!("style", |t| {
+                    style.borrow_mut().push_str(t.as_str());
+                    if t.last_in_text_node()
+                    {
+                        let css = self.inlineCss(&style.borrow(), &base.borrow(), 1);
+                        t.replace(&css.replace("</style", "<\\/style"), ContentType::Html);
+                        style.borrow_mut().clear();
+                    }
+                    else
+                    {
+                        t.remove();
+                    }
+                    Ok(())
+                }),
+                element!("script", |el| {
+                    if disable_js
+                    {
+                        el.remove();
+                    }
+                    else if let Some(src) = el.get_attribute("src")
+                    {
+                        let src = html::unescape(&src);
+                        el.set_attribute("src", &self.inlineUrl(&src, &base.borrow()))?;
+                        el.remove_attribute("integrity");
+                    }
+                    Ok(())
+                }),
+                element!("noscript", |el| {
+                    if disable_js
+                    {
+                        el.remove_and_keep_content();
+                    }
+                    Ok(())
+                }),
+                element!("*", |el| {
+                    if disable_js
+                    {
+                        let handlers: Vec<String> = el.attributes().iter()
+                            .map(|a| a.name())
+                            .filter(|name| name.starts_with("on")).collect();
+                        for name in handlers
+                        {
+                            el.remove_attribute(&name);
+                        }
+                    }
+                    Ok(())
+                }),
+            ],
+            ..RewriteStrSettings::new()
+        };
+        let mut result = rewrite_str(doc, settings)
+            .map_err(|e| rterr!("Failed to process HTML: {}", e))?;
+        if !has_head.get()
+        {
+            // The head can be omitted. A <meta> before anything else
+            // goes into the implied head, but it should be after the
+            // doctype, which would otherwise be ignored.
+            let doctype = Regex::new(r"(?i)^\s*<!doctype[^>]*>").unwrap();
+            let at = doctype.find(&result).map(|m| m.end()).unwrap_or(0);
+            result.insert_str(at, ISOLATION);
+        }
+        Ok(result)
+    }
+
+    /// Fetch the web page at `url` and inline all its resources.
+    pub fn inlinePage(&self, url: &str) -> Result<String, Error>
+    {
+        let u = Url::parse(url).map_err(|_| rterr!("Invalid URL: {}", url))?;
+        let res = self.fetch(&u)?;
+        self.inlineHtml(&decodeHtml(&res.data, res.charset), &res.url)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use anyhow::Result;
+
+    /// Create an inliner that knows the resources in `resources`
+    /// without going to the network.
+    fn inliner(resources: &[(&str, &str)], download_font: bool, disable_js: bool) -> Inliner
+    {
+        let inliner = Inliner::new(download_font, disable_js);
+        for (u, data) in resources
+        {
+            inliner.cache.borrow_mut().insert(u.to_string(), Some(data.to_string()));
+        }
+        inliner
+    }
+
+    #[test]
+    fn css() -> Result<()>
+    {
+        let base = Url::parse("https://example.com/css/main.css")?;
+        let inliner = inliner(&[("https://example.com/img/bg.png", "data:image/png;base64,AA=="),
+                                ("https://example.com/font.woff", "data:font/woff;base64,AA==")],
+                              false, false);
+        assert_eq!(inliner.inlineCss("body { background: url('../img/bg.png'); }", &base, 1),
+                   "body { background: url(\"data:image/png;base64,AA==\"); }");
+        assert_eq!(inliner.inlineCss("@font-face { src: url(/font.woff); } p {}", &base, 1),
+                   " p {}");
+        let inliner = Inliner { download_font: true, ..inliner };
+        assert_eq!(inliner.inlineCss("@font-face { src: url(/font.woff); }", &base, 1),
+                   "@font-face { src: url(\"data:font/woff;base64,AA==\"); }");
+        Ok(())
+    }
+
+    #[test]
+    fn page() -> Result<()>
+    {
+        let doc = r##"<html><head><meta charset="iso-8859-1"><base href="/a/">
+<link rel="stylesheet" href="data:text/css,p{}"><link rel="preload" href="x.js">
+<style>p { background: url(bg.png) }</style></head>
+<body><img src="x.png?a=1&amp;b=2" srcset="x.png 1x, y.png 2x" onclick="f()">
+<a href="page.html">Page</a><a href="#top">Top</a>
+<script src="x.js"></script><noscript><p>No JS</p></noscript></body></html>"##;
+        let url = Url::parse("https://example.com/index.html")?;
+        let inliner = inliner(&[("https://example.com/a/bg.png", "data:bg"),
+                                ("https://example.com/a/x.png?a=1&b=2", "data:x1"),
+                                ("https://example.com/a/x.png", "data:x"),
+                                ("https://example.com/a/y.png", "data:y"),
+                                ("https://example.com/a/x.js", "data:js")],
+                              false, true);
+        let result = inliner.inlineHtml(doc, &url)?;
+        assert!(result.starts_with(&format!("<html><head>{}", ISOLATION)));
+        assert!(!result.contains("iso-8859-1"));
+        assert!(!result.contains("<base"));
+        assert!(!result.contains("preload"));
+        assert!(result.contains("p { background: url(\"data:bg\") }"));
+        assert!(result.contains(r#"<img src="data:x1" srcset="data:x 1x, data:y 2x">"#));
+        assert!(result.contains(r#"<a href="https://example.com/a/page.html">"#));
+        assert!(result.contains(r##"<a href="#top">"##));
+        assert!(!result.contains("<script"));
+        assert!(result.contains("<p>No JS</p>"));
+        assert!(!result.contains("noscript"));
+        Ok(())
+    }
+
+    #[test]
+    fn headless() -> Result<()>
+    {
+        let url = Url::parse("https://example.com/")?;
+        let inliner = inliner(&[], false, false);
+        let result = inliner.inlineHtml(
+            "<!DOCTYPE html><meta charset=\"iso-8859-1\"><title>T</title><p>Café</p>", &url)?;
+        assert_eq!(result, format!("<!DOCTYPE html>{}<title>T</title><p>Café</p>", ISOLATION));
+        let result = inliner.inlineHtml("<body><p>Café</p></body>", &url)?;
+        assert_eq!(result, format!("{}<body><p>Café</p></body>", ISOLATION));
+        let result = inliner.inlineHtml("<html><head></head><body></body></html>", &url)?;
+        assert_eq!(result.matches("Content-Security-Policy").count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn encodings()
+    {
+        // “Café ü” in Latin-1 and Shift_JIS “日本”.
+        let latin1 = b"<html><head><meta charset=\"iso-8859-1\"></head><p>Caf\xe9 \xfc</p>";
+        let decoded = decodeHtml(latin1, None);
+        assert!(decoded.contains("<p>Café ü</p>"));
+        let sjis = b"<meta http-equiv=\"Content-Type\" content=\"text/html; charset=Shift_JIS\">\x93\xfa\x96\x7b";
+        assert!(decodeHtml(sjis, None).ends_with("日本"));
+        // The header wins over the <meta>, and the byte order mark
+        // wins over both.
+        assert!(decodeHtml(b"<meta charset=\"utf-8\">\x93\xfa\x96\x7b",
+                           charsetOf("text/html; charset=\"shift_jis\""))
+                .ends_with("日本"));
+        assert_eq!(decodeHtml(b"\xef\xbb\xbf\xc3\xa9", Some(WINDOWS_1252)), "é");
+        assert_eq!(decodeHtml("é".as_bytes(), None), "é");
+        assert_eq!(decodeHtml(b"\xe9", None), "é");
+        assert_eq!(charsetOf("text/html"), None);
+
+        assert_eq!(decodeCss(b"@charset \"iso-8859-1\";p::before { content: \"\xe9\" }", None),
+                   "p::before { content: \"é\" }");
+        assert_eq!(decodeCss(b"\xef\xbb\xbf@charset \"utf-8\";p {}", None), "p {}");
+        assert_eq!(decodeCss(b"p { content: \"\xe9\" }", Some(WINDOWS_1252)),
+                   "p { content: \"é\" }");
+
+        // The inlined page says it is UTF-8, and it is.
+        let url = Url::parse("https://example.com/").unwrap();
+        let result = inliner(&[], false, false).inlineHtml(&decoded, &url).unwrap();
+        assert!(result.contains(r#"<meta charset="utf-8">"#));
+        assert!(!result.contains("iso-8859-1"));
+        assert!(result.contains("<p>Café ü</p>"));
+    }
+
+    #[test]
+    fn unescape()
+    {
+        assert_eq!(html::unescape("a&amp;b &lt;&#39;&#x41;&gt; &unknown; & x"),
+                   "a&b <'A> &unknown; & x");
+    }
+}
@@ -3,12 +3,28 @@
 #[macro_use]
 mod error;
 mod analyser;
+mod arxiv;
+mod article;
+mod bluesky;
+mod chromium;
+mod external;
+mod github;
+mod html;
+mod inliner;
 mod records;
+mod reddit;
 mod config;
+mod discussion;
 mod env;
+mod mastodon;
 mod organizer;
+mod registry;
 mod runtime_config;
 mod twitter;
+mod twitter_archive;
+mod twitter_timeline;
+mod video;
+mod warc;
 mod webpage;
 
 use std::path::Path;
@@ -17,6 +33,8 @@ use log::warn;
 use crate::error::Error;
 use crate::config::Config;
 use crate::records::ListItem;
+use crate::registry::Registry;
+use crate::twitter_timeline::Timeline;
 
 fn formatPath(path: &Path) -> Result<String, Error>
 {
@@ -56,6 +74,15 @@ fn getConfig() -> Result<Config, Error>
     Config::fromFile(&conf_file)
 }
 
+fn categoryArg() -> clap::Arg
+{
+    clap::Arg::new("category")
+        .short('c')
+        .long("category")
+        .default_value("")
+        .help("The category of the records. Default: Place records at root")
+}
+
 fn cli() -> Result<(), Error>
 {
     simple_logger::init_with_level(log::Level::Info).map_err(
@@ -67,9 +94,10 @@ fn cli() -> Result<(), Error>
         .subcommand(
             clap::Command::new("record")
                 .about("Archive an URL")
+                .allow_missing_positional(true)
                 .arg(clap::Arg::new("TITLE")
-                     .required(true)
-                     .help("The title of the record"))
+                     .help("The title of the record. Default: the title \
+                            found by the analyser, if there is one"))
                 .arg(clap::Arg::new("URL")
                     .required(true)
                      .help("The URL to record"))
@@ -90,9 +118,69 @@ fn cli() -> Result<(), Error>
                      .long("disable-js")
                      .action(clap::ArgAction::SetTrue)
                      .help("Disable JavaScript when using \
-                            the web page downloader.")))
+                            the web page downloader."))
+                .arg(clap::Arg::new("warc")
+                     .short('w')
+                     .long("warc")
+                     .action(clap::ArgAction::SetTrue)
+                     .help("Also save the HTTP traffic as a WARC file when \
+                            using the web page downloader."))
+                .arg(clap::Arg::new("thread")
+                     .short('t')
+                     .long("thread")
+                     .action(clap::ArgAction::SetTrue)
+                     .help("Archive the whole thread by the author \
+                            when recording a tweet.")))
+        .subcommand(
+            clap::Command::new("import")
+                .about("Import records from other sources")
+                .subcommand_required(true)
+                .subcommand(
+                    clap::Command::new("twitter-archive")
+                        .about("Import tweets from a Twitter data export, \
+                                one record per tweet")
+                        .arg(clap::Arg::new("PATH")
+                             .required(true)
+                             .help("The zip file of the export, or the \
+                                    directory it is extracted to"))
+                        .arg(categoryArg())
+                        .arg(clap::Arg::new("likes")
+                             .short('l')
+                             .long("likes")
+                             .action(clap::ArgAction::SetTrue)
+                             .help("Import the liked tweets instead of \
+                                    the tweets of the account"))))
+        .subcommand(
+            clap::Command::new("twitter")
+                .about("Archive tweets from a Twitter account, one record \
+                        per tweet. Only tweets newer than the last run are \
+                        archived.")
+                .subcommand_required(true)
+                .subcommand(
+                    clap::Command::new("timeline")
+                        .about("Archive the tweets of an account")
+                        .arg(clap::Arg::new("HANDLE").required(true))
+                        .arg(categoryArg()))
+                .subcommand(
+                    clap::Command::new("likes")
+                        .about("Archive the tweets liked by an account. \
+                                Needs StaticToken authentication.")
+                        .arg(clap::Arg::new("HANDLE").required(true))
+                        .arg(categoryArg()))
+                .subcommand(
+                    clap::Command::new("bookmarks")
+                        .about("Archive the bookmarks of the authenticated \
+                                account. Needs StaticToken authentication \
+                                and an OAuth 2.0 user token.")
+                        .arg(categoryArg())))
         .subcommand(clap::Command::new("list")
                     .about("List all categories and records"))
+        .subcommand(
+            clap::Command::new("analysers")
+                .about("List the registered analysers")
+                .arg(clap::Arg::new("URL")
+                     .help("Show which analyser would be chosen \
+                            for this URL")))
         .get_matches();
 
     let mut config = getConfig()?;
@@ -105,10 +193,45 @@ fn cli() -> Result<(), Error>
                 *sub_opts.get_one::<bool>("download-font").unwrap();
             config.single_page_config.disable_js =
                 *sub_opts.get_one::<bool>("disable-js").unwrap();
+            if *sub_opts.get_one::<bool>("warc").unwrap()
+            {
+                config.single_page_config.warc = true;
+            }
+            if *sub_opts.get_one::<bool>("thread").unwrap()
+            {
+                config.twitter_config.thread = true;
+            }
             let url = sub_opts.get_one::<String>("URL").unwrap();
-            let title = sub_opts.get_one::<String>("TITLE").unwrap();
+            let title = sub_opts.get_one::<String>("TITLE");
             let cat = sub_opts.get_one::<String>("category").unwrap();
-            records::make(url, title, &cat, &config)?;
+            records::make(url, title.map(|t| t.as_str()), &cat, &config)?;
+        },
+        Some(("import", sub_opts)) =>
+        {
+            if let Some(("twitter-archive", import_opts)) = sub_opts.subcommand()
+            {
+                let path = import_opts.get_one::<String>("PATH").unwrap();
+                let cat = import_opts.get_one::<String>("category").unwrap();
+                let likes = *import_opts.get_one::<bool>("likes").unwrap();
+                let count = twitter_archive::import(Path::new(path), cat, likes,
+                                                    &config)?;
+                log::info!("Imported {} records.", count);
+            }
+        },
+        Some(("twitter", sub_opts)) =>
+        {
+            let (timeline, timeline_opts) = match sub_opts.subcommand()
+            {
+                Some(("timeline", o)) => (Timeline::Tweets, o),
+                Some(("likes", o)) => (Timeline::Likes, o),
+                Some(("bookmarks", o)) => (Timeline::Bookmarks, o),
+                _ => return Ok(()),
+            };
+            let handle = timeline_opts.try_get_one::<String>("HANDLE")
+                .ok().flatten().map(|s| s.as_str()).unwrap_or("");
+            let cat = timeline_opts.get_one::<String>("category").unwrap();
+            let count = twitter_timeline::archive(timeline, handle, cat, &config)?;
+            log::info!("Archived {} tweets.", count);
         },
         Some(("list", _)) =>
         {
@@ -122,7 +245,28 @@ fn cli() -> Result<(), Error>
                         println!("R {}", formatPath(&path)?),
                 }
             }
-        }
+        },
+        Some(("analysers", sub_opts)) =>
+        {
+            let registry = Registry::fromConfig(&config)?;
+            for entry in registry.entries()
+            {
+                let rules: Vec<String> = entry.rules.iter()
+                    .map(|rule| rule.to_string()).collect();
+                println!("{:<12} {:>4}  {}", entry.name, entry.priority,
+                         rules.join(", "));
+            }
+            for (pattern, name) in &config.analyser_overrides
+            {
+                println!("Override: {} --> {}", pattern, name);
+            }
+            if let Some(uri) = sub_opts.get_one::<String>("URL")
+            {
+                let u = url::Url::parse(uri).map_err(
+                    |_| rterr!("Invalid URL: {}", uri))?;
+                println!("{} --> {}", uri, registry.choose(&u, &config)?.name);
+            }
+        },
         _ => {}
     }
     Ok(())
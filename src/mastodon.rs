@@ -0,0 +1,390 @@
+use std::path::Path;
+
+use log::{info, warn, debug};
+use serde_json::Value;
+use url::Url;
+
+use crate::analyser;
+use crate::analyser::{Analysis, RecordInfo, ResourceAnalyser, ResourceInfo,
+                      TempItem, UrlRule};
+use crate::error::Error;
+use crate::html;
+
+static ACTIVITY_JSON: &str = "application/activity+json";
+
+/// The URLs handled by the Mastodon client. There is no telling which
+/// hosts are Mastodon instances, so this is based on the path alone.
+pub fn urlRules() -> Result<Vec<UrlRule>, Error>
+{
+    Ok(vec![
+        UrlRule::withPath("*", r"^/@[^/]+/\d+/?$")?,
+        UrlRule::withPath("*", r"^/users/[^/]+/statuses/\d+/?$")?,
+    ])
+}
+
+/// Get the host and the status ID of the status at `url`.
+fn statusId(url: &str) -> Result<(String, String), Error>
+{
+    let u = Url::parse(url).map_err(|_| rterr!("Invalid URL: {}", url))?;
+    let host = u.host_str().ok_or_else(|| rterr!("URL should have a host"))?;
+    let id = u.path().trim_end_matches('/').rsplit('/').next()
+        .filter(|id| !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit()))
+        .ok_or_else(|| rterr!("Not a status URL: {}", url))?;
+    Ok((host.to_owned(), id.to_owned()))
+}
+
+fn getJson(url: &str, accept: &str) -> Result<Value, Error>
+{
+    debug!("Getting {}...", url);
+    let agent = ureq::builder()
+        .timeout_read(std::time::Duration::from_secs(10))
+        .build();
+    let res = agent.get(url).set("Accept", accept).call()
+        .map_err(|e| rterr!("Failed to get {}: {}", url, e))?;
+    let body = res.into_string().map_err(
+        |e| rterr!("Failed to read response from {}: {}", url, e))?;
+    serde_json::from_str(&body)
+        .map_err(|_| rterr!("Invalid JSON response from {}", url))
+}
+
+/// A crude plain text version of the HTML `content` of a status.
+fn htmlToText(content: &str) -> String
+{
+    let content = content.replace("<br>", "\n").replace("<br/>", "\n")
+        .replace("<br />", "\n").replace("</p>", "\n\n");
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in content.chars()
+    {
+        match c
+        {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            _ if !in_tag => text.push(c),
+            _ => {},
+        }
+    }
+    html::unescape(&text).trim().to_owned()
+}
+
+fn gcd(a: u64, b: u64) -> u64
+{
+    if b == 0 {a} else {gcd(b, a % b)}
+}
+
+/// Get the resource info of a media attachment of a status.
+fn attachmentInfo(attachment: &Value) -> ResourceInfo
+{
+    let original = &attachment["meta"]["original"];
+    let aspect_ratio = match (original["width"].as_u64(), original["height"].as_u64())
+    {
+        (Some(w), Some(h)) if w > 0 && h > 0 =>
+            Some(format!("{}:{}", w / gcd(w, h), h / gcd(w, h))),
+        _ => None,
+    };
+    ResourceInfo {
+        description: attachment["description"].as_str()
+            .filter(|s| !s.is_empty()).map(|s| s.to_owned()),
+        content_type: attachment["mime"].as_str().map(|s| s.to_owned()),
+        duration_ms: original["duration"].as_f64().map(|d| (d * 1000.0) as u64),
+        aspect_ratio,
+        ..ResourceInfo::default()
+    }
+}
+
+/// The full handle of the author of `status`, like
+/// “@user@example.org”.
+fn fullHandle(status: &Value, host: &str) -> String
+{
+    let acct = status["account"]["acct"].as_str().unwrap_or("");
+    if acct.contains('@')
+    {
+        format!("@{}", acct)
+    }
+    else
+    {
+        format!("@{}@{}", acct, host)
+    }
+}
+
+/// Convert the ActivityPub `note` and its author `actor` into the
+/// form of a status in the Mastodon API, as far as we need.
+fn fromActivityPub(note: &Value, actor: &Value) -> Value
+{
+    let host = actor["id"].as_str().and_then(|u| Url::parse(u).ok())
+        .and_then(|u| u.host_str().map(|h| h.to_owned()))
+        .unwrap_or_default();
+    let attachments: Vec<Value> = note["attachment"].as_array().into_iter()
+        .flatten().map(|a| {
+            let mime = a["mediaType"].as_str().unwrap_or("");
+            let kind = match mime.split('/').next()
+            {
+                Some("image") => "image",
+                Some("video") => "video",
+                Some("audio") => "audio",
+                _ => "unknown",
+            };
+            serde_json::json!({
+                "type": kind,
+                "url": a["url"],
+                "description": a["name"],
+                "mime": mime,
+                "meta": {"original": {"width": a["width"], "height": a["height"]}},
+            })
+        }).collect();
+    serde_json::json!({
+        "id": note["id"],
+        "url": note["url"].as_str().or_else(|| note["id"].as_str()),
+        "created_at": note["published"],
+        "spoiler_text": note["summary"].as_str().unwrap_or(""),
+        "content": note["content"],
+        "account": {
+            "display_name": actor["name"],
+            "acct": format!("{}@{}",
+                            actor["preferredUsername"].as_str().unwrap_or(""), host),
+            "url": actor["url"].as_str().or_else(|| actor["id"].as_str()),
+            "avatar": actor["icon"]["url"],
+        },
+        "media_attachments": attachments,
+    })
+}
+
+/// Render a status into HTML. The content warning is kept, and the
+/// content is folded under it.
+fn renderStatus(status: &Value, host: &str) -> String
+{
+    let account = &status["account"];
+    let mut result = String::from("<div class=\"status\">\n<div class=\"author\">");
+    if let Some(avatar) = account["avatar"].as_str()
+    {
+        result.push_str(&format!("<img class=\"avatar\" src=\"{}\"> ",
+                                 html::escape(avatar)));
+    }
+    result.push_str(&format!(
+        "<b>{}</b> <span class=\"meta\">{}</span></div>\n",
+        html::escape(account["display_name"].as_str().unwrap_or("")),
+        html::escape(&fullHandle(status, host))));
+
+    let mut content = status["content"].as_str().unwrap_or("").to_owned();
+    content.push('\n');
+    for attachment in status["media_attachments"].as_array().into_iter().flatten()
+    {
+        let url = match attachment["url"].as_str()
+        {
+            Some(u) => html::escape(u),
+            None => continue,
+        };
+        let alt = attachment["description"].as_str().map(
+            |d| format!(" alt=\"{}\"", html::escape(d))).unwrap_or_default();
+        content.push_str(&match attachment["type"].as_str()
+        {
+            Some("image") => format!("<p><img src=\"{}\"{}></p>\n", url, alt),
+            Some("video") | Some("gifv") =>
+                format!("<p><video controls src=\"{}\"></video></p>\n", url),
+            Some("audio") =>
+                format!("<p><audio controls src=\"{}\"></audio></p>\n", url),
+            _ => format!("<p><a href=\"{0}\">{0}</a></p>\n", url),
+        });
+    }
+    match status["spoiler_text"].as_str()
+    {
+        Some(cw) if !cw.is_empty() => result.push_str(&format!(
+            "<details>\n<summary>{}</summary>\n{}</details>\n",
+            html::escape(cw), content)),
+        _ => result.push_str(&content),
+    }
+
+    let reblog = &status["reblog"];
+    if reblog.is_object()
+    {
+        result.push_str(&format!("<blockquote>\n{}</blockquote>\n",
+                                 renderStatus(reblog, host)));
+    }
+    if let Some(u) = status["url"].as_str()
+    {
+        result.push_str(&format!(
+            "<div class=\"meta\"><a href=\"{}\">{}</a></div>\n",
+            html::escape(u),
+            html::escape(status["created_at"].as_str().unwrap_or(u))));
+    }
+    result.push_str("</div>\n");
+    result
+}
+
+/// Add the content, content warning and attachments of `status` to
+/// `analysis`, along with the status it boosts.
+fn addStatus(status: &Value, relation: Option<&str>, analysis: &mut Analysis)
+{
+    let relation = relation.map(|r| r.to_owned());
+    if let Some(cw) = status["spoiler_text"].as_str().filter(|s| !s.is_empty())
+    {
+        analysis.addWithInfo(TempItem::Text(cw.to_owned()), ResourceInfo {
+            role: Some(String::from("content_warning")),
+            relation: relation.clone(),
+            ..ResourceInfo::default()
+        });
+    }
+    let text = htmlToText(status["content"].as_str().unwrap_or(""));
+    if !text.is_empty()
+    {
+        analysis.addWithInfo(TempItem::Text(text), ResourceInfo {
+            relation: relation.clone(),
+            ..ResourceInfo::default()
+        });
+    }
+    for attachment in status["media_attachments"].as_array().into_iter().flatten()
+    {
+        if let Some(u) = attachment["url"].as_str()
+        {
+            info!("Found Mastodon media at {}.", u);
+            analysis.addWithInfo(TempItem::Url(u.to_owned()), ResourceInfo {
+                relation: relation.clone(),
+                ..attachmentInfo(attachment)
+            });
+        }
+    }
+    if status["reblog"].is_object()
+    {
+        addStatus(&status["reblog"], Some("reblogged"), analysis);
+    }
+}
+
+/// The Mastodon client. If the URL turns out not to be a status on a
+/// Mastodon (or other ActivityPub) server, it is handled by `fallback`.
+pub struct Client
+{
+    fallback: Box<dyn ResourceAnalyser>,
+}
+
+impl Client
+{
+    pub fn new(fallback: Box<dyn ResourceAnalyser>) -> Self
+    {
+        Self { fallback }
+    }
+
+    /// Get the status at `url` as in the Mastodon API. Try the API of
+    /// the server first, then the ActivityPub object.
+    fn getStatus(&self, url: &str) -> Result<(Value, String), Error>
+    {
+        let (host, id) = statusId(url)?;
+        let status = getJson(&format!("https://{}/api/v1/statuses/{}", host, id),
+                             "application/json");
+        match status
+        {
+            Ok(s) if s["content"].is_string() => return Ok((s, host)),
+            Ok(_) => debug!("{} does not have the Mastodon API.", host),
+            Err(e) => debug!("{}", e),
+        }
+
+        let mut note = getJson(url, ACTIVITY_JSON)?;
+        if note["type"].as_str() == Some("Create")
+        {
+            note = note["object"].clone();
+        }
+        if !note["content"].is_string()
+        {
+            return Err(rterr!("{} is not an ActivityPub note", url));
+        }
+        let actor = match &note["attributedTo"]
+        {
+            Value::String(u) => getJson(u, ACTIVITY_JSON)?,
+            a => a.clone(),
+        };
+        Ok((fromActivityPub(&note, &actor), host))
+    }
+}
+
+impl analyser::ResourceAnalyser for Client
+{
+    fn analyse(&self, url: &str, scratch: &Path) -> Result<Analysis, Error>
+    {
+        let (status, host) = match self.getStatus(url)
+        {
+            Ok(s) => s,
+            Err(e) =>
+            {
+                warn!("Failed to get Mastodon status: {}. \
+                       Archiving as a web page...", e);
+                return self.fallback.analyse(url, scratch);
+            },
+        };
+
+        let mut analysis = Analysis::new();
+        analysis.info = RecordInfo {
+            title: None,
+            authors: vec![fullHandle(&status, &host)],
+            published: status["created_at"].as_str().map(|s| s.to_owned()),
+        };
+        addStatus(&status, None, &mut analysis);
+        if let Some(avatar) = status["account"]["avatar"].as_str()
+        {
+            analysis.addWithRole(TempItem::Url(avatar.to_owned()), "avatar");
+        }
+        let raw = serde_json::to_string_pretty(&status)
+            .map_err(|_| rterr!("Failed to serialize status"))?;
+        analysis.addWithRole(TempItem::Json(raw), "raw");
+        let title = format!("{} on {}", status["account"]["display_name"]
+                            .as_str().unwrap_or(""), host);
+        analysis.addWithRole(TempItem::Html(html::page(
+            &title, &renderStatus(&status, &host))), "page");
+        Ok(analysis)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn urls() -> Result<()>
+    {
+        assert_eq!(statusId("https://mastodon.social/@Gargron/109318821117356215")?,
+                   (String::from("mastodon.social"), String::from("109318821117356215")));
+        assert_eq!(statusId("https://example.org/users/someone/statuses/123/")?.1, "123");
+        assert!(statusId("https://mastodon.social/@Gargron").is_err());
+        let rules = urlRules()?;
+        let matches = |u: &str| rules.iter().any(|r| r.matches(&Url::parse(u).unwrap()));
+        assert!(matches("https://mastodon.social/@Gargron/109318821117356215"));
+        assert!(matches("https://example.org/@someone@other.org/123"));
+        assert!(!matches("https://medium.com/@someone/some-title-1a2b3c"));
+        Ok(())
+    }
+
+    #[test]
+    fn activityPub() -> Result<()>
+    {
+        let note: Value = serde_json::from_str(r#"{
+            "id": "https://example.org/users/a/statuses/1", "type": "Note",
+            "url": "https://example.org/@a/1",
+            "summary": "Food", "content": "<p>Lunch &amp; coffee</p>",
+            "published": "2022-11-23T06:00:00Z",
+            "attachment": [{"type": "Document", "mediaType": "image/jpeg",
+                            "url": "https://example.org/a.jpg", "name": "A sandwich",
+                            "width": 1920, "height": 1080}]}"#)?;
+        let actor: Value = serde_json::from_str(r#"{
+            "id": "https://example.org/users/a", "preferredUsername": "a",
+            "name": "Some One", "icon": {"url": "https://example.org/avatar.png"}}"#)?;
+        let status = fromActivityPub(&note, &actor);
+        assert_eq!(fullHandle(&status, "other.org"), "@a@example.org");
+
+        let mut analysis = Analysis::new();
+        addStatus(&status, None, &mut analysis);
+        assert_eq!(analysis.resources.len(), 3);
+        assert_eq!(analysis.resources[0].item, TempItem::Text(String::from("Food")));
+        assert_eq!(analysis.resources[0].info.role.as_deref(), Some("content_warning"));
+        assert_eq!(analysis.resources[1].item,
+                   TempItem::Text(String::from("Lunch & coffee")));
+        let info = &analysis.resources[2].info;
+        assert_eq!(info.description.as_deref(), Some("A sandwich"));
+        assert_eq!(info.content_type.as_deref(), Some("image/jpeg"));
+        assert_eq!(info.aspect_ratio.as_deref(), Some("16:9"));
+
+        let page = renderStatus(&status, "example.org");
+        assert!(page.contains("<summary>Food</summary>"));
+        assert!(page.contains(r#"<img src="https://example.org/a.jpg" alt="A sandwich">"#));
+        Ok(())
+    }
+}
@@ -8,9 +8,11 @@ use serde::{Serialize, Deserialize};
 use quick_xml::events::{Event, BytesEnd, BytesStart, BytesText};
 use quick_xml::{Reader, Writer};
 use md5::{Md5, Digest};
+use url::Url;
 
 use crate::error::Error;
-use crate::analyser::TempItem;
+use crate::analyser::{Analysis, RecordInfo, Resource, TempItem};
+use crate::html;
 
 pub static METADATA_FILE: &str = "metadata.xml";
 
@@ -19,6 +21,13 @@ struct ResourceMetadata
 {
     filename: String,
     url: Option<String>,
+    role: Option<String>,
+    relation: Option<String>,
+    description: Option<String>,
+    content_type: Option<String>,
+    duration_ms: Option<u64>,
+    aspect_ratio: Option<String>,
+    original_name: Option<String>,
 }
 
 fn writeXMLTagBegin<W: Write>(tag: &str, writer: &mut Writer<W>) ->
@@ -53,12 +62,22 @@ impl ResourceMetadata
         {
             Filename,
             Url,
+            Role,
+            Relation,
+            Description,
+            ContentType,
+            DurationMs,
+            AspectRatio,
+            OriginalName,
             Unknown,
             Stop,
         }
 
         let mut state = State::Unknown;
-        let mut result = Self { filename: String::new(), url: None };
+        let mut result = Self { filename: String::new(), url: None,
+                                role: None, relation: None, description: None,
+                                content_type: None, duration_ms: None,
+                                aspect_ratio: None, original_name: None };
         let mut buffer = Vec::new();
 
         while state != State::Stop
@@ -74,6 +93,34 @@ impl ResourceMetadata
                     {
                         state = State::Url;
                     }
+                    else if e.name().as_ref() == b"role"
+                    {
+                        state = State::Role;
+                    }
+                    else if e.name().as_ref() == b"relation"
+                    {
+                        state = State::Relation;
+                    }
+                    else if e.name().as_ref() == b"description"
+                    {
+                        state = State::Description;
+                    }
+                    else if e.name().as_ref() == b"content_type"
+                    {
+                        state = State::ContentType;
+                    }
+                    else if e.name().as_ref() == b"duration_ms"
+                    {
+                        state = State::DurationMs;
+                    }
+                    else if e.name().as_ref() == b"aspect_ratio"
+                    {
+                        state = State::AspectRatio;
+                    }
+                    else if e.name().as_ref() == b"original_name"
+                    {
+                        state = State::OriginalName;
+                    }
                     else
                     {
                         return Err(rterr!("Invalid element in resource"));
@@ -107,6 +154,56 @@ impl ResourceMetadata
                                 .into_owned();
                             result.url = Some(u);
                         },
+                        State::Role =>
+                        {
+                            let r = inner.unescape().map_err(
+                                |_| rterr!("Invalid role in XML"))?
+                                .into_owned();
+                            result.role = Some(r);
+                        },
+                        State::Relation =>
+                        {
+                            let r = inner.unescape().map_err(
+                                |_| rterr!("Invalid relation in XML"))?
+                                .into_owned();
+                            result.relation = Some(r);
+                        },
+                        State::Description =>
+                        {
+                            let d = inner.unescape().map_err(
+                                |_| rterr!("Invalid description in XML"))?
+                                .into_owned();
+                            result.description = Some(d);
+                        },
+                        State::ContentType =>
+                        {
+                            let t = inner.unescape().map_err(
+                                |_| rterr!("Invalid content type in XML"))?
+                                .into_owned();
+                            result.content_type = Some(t);
+                        },
+                        State::DurationMs =>
+                        {
+                            let d = inner.unescape().map_err(
+                                |_| rterr!("Invalid duration in XML"))?
+                                .parse().map_err(
+                                    |_| rterr!("Invalid duration in XML"))?;
+                            result.duration_ms = Some(d);
+                        },
+                        State::AspectRatio =>
+                        {
+                            let r = inner.unescape().map_err(
+                                |_| rterr!("Invalid aspect ratio in XML"))?
+                                .into_owned();
+                            result.aspect_ratio = Some(r);
+                        },
+                        State::OriginalName =>
+                        {
+                            let n = inner.unescape().map_err(
+                                |_| rterr!("Invalid original name in XML"))?
+                                .into_owned();
+                            result.original_name = Some(n);
+                        },
                         _ => {},
                     }
                 },
@@ -133,6 +230,48 @@ impl ResourceMetadata
             writeXMLText(&u, writer)?;
             writeXMLTagEnd("url", writer)?;
         }
+        if let Some(r) = &self.role
+        {
+            writeXMLTagBegin("role", writer)?;
+            writeXMLText(r, writer)?;
+            writeXMLTagEnd("role", writer)?;
+        }
+        if let Some(r) = &self.relation
+        {
+            writeXMLTagBegin("relation", writer)?;
+            writeXMLText(r, writer)?;
+            writeXMLTagEnd("relation", writer)?;
+        }
+        if let Some(d) = &self.description
+        {
+            writeXMLTagBegin("description", writer)?;
+            writeXMLText(d, writer)?;
+            writeXMLTagEnd("description", writer)?;
+        }
+        if let Some(t) = &self.content_type
+        {
+            writeXMLTagBegin("content_type", writer)?;
+            writeXMLText(t, writer)?;
+            writeXMLTagEnd("content_type", writer)?;
+        }
+        if let Some(d) = self.duration_ms
+        {
+            writeXMLTagBegin("duration_ms", writer)?;
+            writeXMLText(&d.to_string(), writer)?;
+            writeXMLTagEnd("duration_ms", writer)?;
+        }
+        if let Some(r) = &self.aspect_ratio
+        {
+            writeXMLTagBegin("aspect_ratio", writer)?;
+            writeXMLText(r, writer)?;
+            writeXMLTagEnd("aspect_ratio", writer)?;
+        }
+        if let Some(n) = &self.original_name
+        {
+            writeXMLTagBegin("original_name", writer)?;
+            writeXMLText(n, writer)?;
+            writeXMLTagEnd("original_name", writer)?;
+        }
         writeXMLTagEnd("resource", writer)
     }
 }
@@ -142,6 +281,8 @@ struct Metadata
     title: String,
     time: OffsetDateTime,
     url: String,
+    /// Information about the original content.
+    source: RecordInfo,
     resources: Vec<ResourceMetadata>,
 }
 
@@ -153,6 +294,7 @@ impl Metadata
             title: String::new(),
             time: OffsetDateTime::UNIX_EPOCH,
             url: String::new(),
+            source: RecordInfo::default(),
             resources: Vec::new(),
         }
     }
@@ -167,6 +309,9 @@ impl Metadata
             Title,
             Time,
             Url,
+            SourceTitle,
+            Author,
+            Published,
             Resources,
             Unknown,
             Stop,
@@ -188,6 +333,9 @@ impl Metadata
                         b"title" => state = State::Title,
                         b"time" => state = State::Time,
                         b"url" => state = State::Url,
+                        b"source_title" => state = State::SourceTitle,
+                        b"author" => state = State::Author,
+                        b"published" => state = State::Published,
                         b"resources" => state = State::Resources,
                         b"resource" =>
                         {
@@ -237,6 +385,27 @@ impl Metadata
                                 |_| rterr!("Invalid URL in XML"))?
                                 .into_owned();
                         },
+                        State::SourceTitle =>
+                        {
+                            result.source.title = Some(
+                                inner.unescape().map_err(
+                                    |_| rterr!("Invalid source title in XML"))?
+                                    .into_owned());
+                        },
+                        State::Author =>
+                        {
+                            result.source.authors.push(
+                                inner.unescape().map_err(
+                                    |_| rterr!("Invalid author in XML"))?
+                                    .into_owned());
+                        },
+                        State::Published =>
+                        {
+                            result.source.published = Some(
+                                inner.unescape().map_err(
+                                    |_| rterr!("Invalid publish time in XML"))?
+                                    .into_owned());
+                        },
                         _ => {},
                     }
                 },
@@ -276,6 +445,25 @@ impl Metadata
         writeXMLText(&self.url, writer)?;
         writeXMLTagEnd("url", writer)?;
 
+        if let Some(t) = &self.source.title
+        {
+            writeXMLTagBegin("source_title", writer)?;
+            writeXMLText(t, writer)?;
+            writeXMLTagEnd("source_title", writer)?;
+        }
+        for author in &self.source.authors
+        {
+            writeXMLTagBegin("author", writer)?;
+            writeXMLText(author, writer)?;
+            writeXMLTagEnd("author", writer)?;
+        }
+        if let Some(t) = &self.source.published
+        {
+            writeXMLTagBegin("published", writer)?;
+            writeXMLText(t, writer)?;
+            writeXMLTagEnd("published", writer)?;
+        }
+
         writeXMLTagBegin("resources", writer)?;
         for resource in &self.resources
         {
@@ -330,10 +518,32 @@ fn hashFile(filename: &Path) -> Result<String, Error>
     Ok(hashData(&data))
 }
 
+/// Extensions of files that are compressed after being packed, which
+/// are kept as a whole for file resources.
+static COMPOUND_EXTENSIONS: &[&str] = &["tar.gz", "tar.xz", "tar.bz2", "warc.gz"];
+
+/// The extension of filename or URL path `name`, if it looks like
+/// one.
+fn extensionOf(name: &str) -> Option<&str>
+{
+    let base = name.rsplit('/').next()?;
+    let (stem, ext) = base.rsplit_once('.')?;
+    if !stem.is_empty() && (1..=5).contains(&ext.len()) &&
+        ext.chars().all(|c| c.is_ascii_alphanumeric())
+    {
+        Some(ext)
+    }
+    else
+    {
+        None
+    }
+}
+
 /// Download the resource at `url` into a file in directory `dir`. The
 /// filename is the hash of the content with a detected extension
-/// name.
-fn download(url: &str, dir: &Path) -> Result<PathBuf, Error>
+/// name. The extension of `name_hint` is preferred if it is given.
+fn download(url: &str, name_hint: Option<&str>, dir: &Path) ->
+    Result<PathBuf, Error>
 {
     // Download file
     let res = ureq::get(url).call().map_err(
@@ -352,21 +562,34 @@ fn download(url: &str, dir: &Path) -> Result<PathBuf, Error>
     };
 
     let final_url = res.get_url().to_owned();
+    let content_type = res.content_type().to_owned();
     res.into_reader().take(1_000_000_000).read_to_end(&mut data).map_err(
         |e| rterr!("Failed to download {}: {}", url, e))?;
 
     // Try to detect content type
-    let res = ureq::head(&final_url).call().map_err(
-        |e| rterr!("Failed to get header from {}: {}", url, e))?;
-    let ext_name = match res.content_type()
+    let ext_name = match content_type.as_str()
     {
         "video/mp4" => "mp4",
+        "video/webm" => "webm",
         "image/jpeg" => "jpg",
         "image/png" => "png",
+        "image/gif" => "gif",
         "image/heic" => "heic",
         "image/webp" => "webp",
+        "image/svg+xml" => "svg",
+        "audio/mpeg" => "mp3",
+        "audio/ogg" => "ogg",
+        "application/pdf" => "pdf",
+        "application/zip" => "zip",
+        "application/gzip" | "application/x-gzip" => "gz",
         _ => "bin",
     };
+    let path = Url::parse(&final_url).map(|u| u.path().to_owned())
+        .unwrap_or_default();
+    let ext_name = name_hint.and_then(extensionOf)
+        .or(if ext_name == "bin" {None} else {Some(ext_name)})
+        .or_else(|| extensionOf(&path))
+        .unwrap_or(ext_name);
 
     // Write downloaded file
     let filename = format!("{}.{}", hashData(&data), ext_name);
@@ -378,10 +601,41 @@ fn download(url: &str, dir: &Path) -> Result<PathBuf, Error>
     Ok(target)
 }
 
+/// The original URL of `resource`, if known.
+fn resourceUrl(resource: &Resource) -> Option<&str>
+{
+    match &resource.item
+    {
+        TempItem::Url(u) => Some(u),
+        _ => resource.info.url.as_deref(),
+    }
+}
+
+/// Replace the attribute values (e.g. of `src` and `href`) in the
+/// HTML document `doc` that are URLs in `local_files` with the
+/// corresponding local filenames. `local_files` is a list of (URL,
+/// filename) pairs.
+fn localizeLinks(doc: &str, local_files: &[(String, String)]) -> String
+{
+    let mut result = doc.to_owned();
+    for (url, filename) in local_files
+    {
+        let escaped = html::escape(url);
+        for u in [&escaped, url]
+        {
+            result = result.replace(&format!("\"{}\"", u),
+                                    &format!("\"{}\"", filename));
+        }
+    }
+    result
+}
+
 /// Record the resource into `dir`. This creates a file in that dir.
 /// Returns the name of the file (only the filename itself, not
-/// including the directory).
-fn recordResource(resource: &TempItem, dir: &Path) -> Result<PathBuf, Error>
+/// including the directory). `name_hint` is the original filename of
+/// a downloaded resource, if known.
+fn recordResource(resource: &TempItem, name_hint: Option<&str>, dir: &Path) ->
+    Result<PathBuf, Error>
 {
     match resource
     {
@@ -392,17 +646,27 @@ fn recordResource(resource: &TempItem, dir: &Path) -> Result<PathBuf, Error>
                 .to_str().ok_or_else(
                     || rterr!("Invalid file name for file resource at {:?}",
                               path))?;
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+            let ext = COMPOUND_EXTENSIONS.iter()
+                .find(|compound| name.ends_with(&format!(".{}", compound)))
+                .copied().unwrap_or(ext);
 
             let hash = hashFile(&path)?;
             let target = dir.join(hash + "." + ext);
             moveFile(&path, &target)?;
             Ok(target)
         },
-        TempItem::Text(s) =>
+        TempItem::Text(s) | TempItem::Html(s) | TempItem::Json(s) =>
         {
+            let ext = match resource
+            {
+                TempItem::Html(_) => ".html",
+                TempItem::Json(_) => ".json",
+                _ => ".txt",
+            };
             let utf8 = s.as_bytes();
             let hash = hashData(utf8);
-            let target = dir.join(hash + ".txt");
+            let target = dir.join(hash + ext);
             let mut f = std::fs::File::create(&target).map_err(
                 |e| rterr!("Failed to open file at {:?}: {}", target, e))?;
             f.write(utf8).map_err(
@@ -411,33 +675,61 @@ fn recordResource(resource: &TempItem, dir: &Path) -> Result<PathBuf, Error>
         },
         TempItem::Url(u) =>
         {
-            download(u, dir)
+            download(u, name_hint, dir)
         },
     }
 }
 
-/// Create a new record from `resources` at a `path`. `Path` should
-/// exit.
-pub fn createRecord(resources: Vec<TempItem>, title: &str, url: &str,
+/// Create a new record from the resources in `analysis` at a `path`.
+/// `Path` should exit.
+pub fn createRecord(analysis: Analysis, title: &str, url: &str,
                     path: &Path) -> Result<(), Error>
 {
-    let mut resources_data: Vec<ResourceMetadata> = Vec::new();
-    for resource in resources
+    // Record the HTML documents last, so that the links in them to
+    // other resources can point to the local files.
+    let mut filenames: Vec<String> = vec![String::new(); analysis.resources.len()];
+    let mut local_files: Vec<(String, String)> = Vec::new();
+    for (i, resource) in analysis.resources.iter().enumerate()
     {
-        let filename: String = recordResource(&resource, path)?
+        if let TempItem::Html(_) = resource.item
+        {
+            continue;
+        }
+        filenames[i] = recordResource(&resource.item,
+                                      resource.info.original_name.as_deref(), path)?
             .file_name().unwrap().to_str().unwrap().to_owned();
-        let url: Option<String> = match resource
+        if let Some(u) = resourceUrl(resource)
         {
-            TempItem::File(_) | TempItem::Text(_) => None,
-            TempItem::Url(u) => Some(u),
-        };
-        resources_data.push(ResourceMetadata { filename, url });
+            local_files.push((u.to_owned(), filenames[i].clone()));
+        }
+    }
+    for (i, resource) in analysis.resources.iter().enumerate()
+    {
+        if let TempItem::Html(doc) = &resource.item
+        {
+            let doc = TempItem::Html(localizeLinks(doc, &local_files));
+            filenames[i] = recordResource(&doc, None, path)?
+                .file_name().unwrap().to_str().unwrap().to_owned();
+        }
+    }
+
+    let mut resources_data: Vec<ResourceMetadata> = Vec::new();
+    for (resource, filename) in analysis.resources.into_iter().zip(filenames)
+    {
+        let url = resourceUrl(&resource).map(|u| u.to_owned());
+        let info = resource.info;
+        resources_data.push(ResourceMetadata {
+            filename, url, role: info.role, relation: info.relation,
+            description: info.description, content_type: info.content_type,
+            duration_ms: info.duration_ms, aspect_ratio: info.aspect_ratio,
+            original_name: info.original_name });
     }
 
     let metadata = Metadata {
         title: title.to_owned(),
         time: OffsetDateTime::now_utc(),
         url: url.to_owned(),
+        source: analysis.info,
         resources: resources_data,
     };
     metadata.writeToFile(&path.join(METADATA_FILE))
@@ -448,14 +740,15 @@ mod tests
 {
     use super::*;
     use anyhow::Result;
+    use crate::analyser::ResourceInfo;
 
     #[test]
     fn download() -> Result<()>
     {
         let temp_dir = tempfile::tempdir()?;
         let dir = temp_dir.path();
-        createRecord(vec![TempItem::Url(
-            String::from("https://picsum.photos/id/123/16"))],
+        createRecord(Analysis::from(vec![TempItem::Url(
+            String::from("https://picsum.photos/id/123/16"))]),
                      "test", "https://google.com", dir)?;
 
         assert!(dir.join("dcc866d76ca96cee9559d124d2c22f8b.jpg").exists());
@@ -467,4 +760,61 @@ mod tests
 
         Ok(())
     }
+
+    #[test]
+    fn sourceInfo() -> Result<()>
+    {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path();
+        let mut analysis = Analysis::new();
+        analysis.addWithRole(TempItem::Text(String::from("Some text")),
+                             "description");
+        analysis.addWithInfo(TempItem::Text(String::from("Quoted text")),
+                             ResourceInfo {
+                                 relation: Some(String::from("quoted")),
+                                 description: Some(String::from("A <cat>")),
+                                 duration_ms: Some(1500),
+                                 aspect_ratio: Some(String::from("16:9")),
+                                 ..ResourceInfo::default()
+                             });
+        analysis.info.title = Some(String::from("Source title"));
+        analysis.info.authors = vec![String::from("A"), String::from("B")];
+        analysis.info.published = Some(String::from("2022-11-23"));
+        createRecord(analysis, "test", "https://google.com", dir)?;
+
+        let data = Metadata::fromFile(&dir.join("metadata.xml"))?;
+        assert_eq!(data.source.title.as_deref(), Some("Source title"));
+        assert_eq!(data.source.authors, vec![String::from("A"), String::from("B")]);
+        assert_eq!(data.source.published.as_deref(), Some("2022-11-23"));
+        assert_eq!(data.resources.len(), 2);
+        assert_eq!(data.resources[0].role.as_deref(), Some("description"));
+        assert_eq!(data.resources[0].relation, None);
+        assert_eq!(data.resources[1].relation.as_deref(), Some("quoted"));
+        assert_eq!(data.resources[1].description.as_deref(), Some("A <cat>"));
+        assert_eq!(data.resources[1].content_type, None);
+        assert_eq!(data.resources[1].duration_ms, Some(1500));
+        assert_eq!(data.resources[1].aspect_ratio.as_deref(), Some("16:9"));
+        Ok(())
+    }
+
+    #[test]
+    fn extensions()
+    {
+        assert_eq!(extensionOf("paper.pdf"), Some("pdf"));
+        assert_eq!(extensionOf("/files/archive.tar.gz"), Some("gz"));
+        assert_eq!(extensionOf("/wiki/Some.Article_title"), None);
+        assert_eq!(extensionOf(".bashrc"), None);
+        assert_eq!(extensionOf("/download"), None);
+    }
+
+    #[test]
+    fn links()
+    {
+        let doc = r#"<img src="https://a.com/1.jpg?a=1&amp;b=2"><a href="https://a.com/2.jpg">https://a.com/2.jpg</a>"#;
+        let local_files = vec![
+            (String::from("https://a.com/1.jpg?a=1&b=2"), String::from("x.jpg")),
+            (String::from("https://a.com/2.jpg"), String::from("y.jpg"))];
+        assert_eq!(localizeLinks(doc, &local_files),
+                   r#"<img src="x.jpg"><a href="y.jpg">https://a.com/2.jpg</a>"#);
+    }
 }
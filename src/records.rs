@@ -1,12 +1,14 @@
+use std::io::Read;
 use std::path::{Path, PathBuf};
 
-use crate::analyser::ResourceAnalyser;
+use log::{info, warn};
+
+use crate::analyser::{Analysis, ResourceInfo, TempItem};
 use crate::error::Error;
 use crate::organizer;
-use crate::twitter;
-use crate::webpage;
 use crate::organizer::createRecord;
-use crate::config::{Config, TwitterAuth};
+use crate::config::Config;
+use crate::registry::Registry;
 
 pub enum ListItem
 {
@@ -88,36 +90,221 @@ pub fn listAll(category: &Path, config: &Config) -> Result<Vec<ListItem>, Error>
     Ok(result)
 }
 
-pub fn make(uri: &str, title: &str, category: &str, conf: &Config) ->
+/// Guess the content type from the first bytes of a file.
+fn sniffContentType(head: &[u8]) -> Option<&'static str>
+{
+    let signatures: &[(&[u8], &str)] = &[
+        (b"%PDF-", "application/pdf"),
+        (b"\x89PNG", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF8", "image/gif"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"ID3", "audio/mpeg"),
+        (b"OggS", "audio/ogg"),
+        (b"\x1a\x45\xdf\xa3", "video/webm"),
+    ];
+    if let Some((_, t)) = signatures.iter().find(|(sig, _)| head.starts_with(sig))
+    {
+        return Some(t);
+    }
+    if head.len() >= 12 && &head[4..8] == b"ftyp"
+    {
+        return Some("video/mp4");
+    }
+    if head.len() >= 12 && head.starts_with(b"RIFF") && &head[8..12] == b"WEBP"
+    {
+        return Some("image/webp");
+    }
+    None
+}
+
+/// Whether a resource of `content_type` is a file that should be
+/// stored as-is, instead of a web page.
+fn isFileType(content_type: &str) -> bool
+{
+    let t = content_type.to_lowercase();
+    t.starts_with("image/") || t.starts_with("audio/") || t.starts_with("video/") ||
+        ["application/pdf", "application/zip", "application/gzip",
+         "application/x-gzip", "application/x-tar", "application/x-bzip2",
+         "application/x-xz", "application/x-7z-compressed", "application/vnd.rar",
+         "application/x-rar-compressed", "application/epub+zip",
+         "application/octet-stream"].contains(&t.as_str())
+}
+
+/// Get the filename in a Content-Disposition header.
+fn dispositionFilename(header: &str) -> Option<String>
+{
+    let params: Vec<(&str, &str)> = header.split(';').skip(1)
+        .filter_map(|p| p.split_once('='))
+        .map(|(k, v)| (k.trim(), v.trim()))
+        .collect();
+    // The RFC 5987 form has the charset and language before the
+    // encoded name, like UTF-8''na%C3%AFve.txt.
+    if let Some((_, v)) = params.iter().find(|(k, _)| k.eq_ignore_ascii_case("filename*"))
+    {
+        if let Some((_, encoded)) = v.split_once("''")
+        {
+            if let Ok(name) = percent_encoding::percent_decode_str(encoded).decode_utf8()
+            {
+                return Some(name.into_owned());
+            }
+        }
+    }
+    params.iter().find(|(k, _)| k.eq_ignore_ascii_case("filename"))
+        .map(|(_, v)| v.trim_matches('"').to_owned())
+        .filter(|name| !name.is_empty())
+}
+
+/// Get the filename in the path of `url`.
+fn urlFilename(url: &str) -> Option<String>
+{
+    let u = url::Url::parse(url).ok()?;
+    let name = u.path_segments()?.next_back()?;
+    let name = percent_encoding::percent_decode_str(name).decode_utf8().ok()?;
+    if name.is_empty() {None} else {Some(name.into_owned())}
+}
+
+/// Check whether `url` points directly to a file, like a PDF or an
+/// image. If it does, return the resource of the file.
+fn directFile(url: &str) -> Result<Option<ResourceInfo>, Error>
+{
+    // Some servers do not support HEAD.
+    let res = match ureq::head(url).call()
+    {
+        Ok(res) => res,
+        Err(_) => ureq::get(url).call().map_err(
+            |e| rterr!("Failed to get {}: {}", url, e))?,
+    };
+    let mut content_type = res.header("Content-Type")
+        .map(|_| res.content_type().to_owned());
+    if content_type.as_deref().map(|t| t == "application/octet-stream")
+        .unwrap_or(true)
+    {
+        let mut head = Vec::new();
+        let res = ureq::get(url).set("Range", "bytes=0-511").call().map_err(
+            |e| rterr!("Failed to get {}: {}", url, e))?;
+        res.into_reader().take(512).read_to_end(&mut head).map_err(
+            |e| rterr!("Failed to read from {}: {}", url, e))?;
+        if let Some(t) = sniffContentType(&head)
+        {
+            content_type = Some(t.to_owned());
+        }
+    }
+    let content_type = match content_type
+    {
+        Some(t) if isFileType(&t) => t,
+        _ => return Ok(None),
+    };
+    let original_name = res.header("Content-Disposition")
+        .and_then(dispositionFilename)
+        .or_else(|| urlFilename(res.get_url()));
+    Ok(Some(ResourceInfo {
+        content_type: Some(content_type),
+        original_name,
+        ..ResourceInfo::default()
+    }))
+}
+
+/// Turn a record `title` into a directory name. Titles that come from
+/// the analysers can be long and contain slashes.
+fn dirName(title: &str) -> String
+{
+    let name: String = title.chars().take(100)
+        .map(|c| if c == '/' || c == '\\' || c.is_control() {'_'} else {c})
+        .collect();
+    let name = name.trim();
+    if name.starts_with('.') {format!("_{}", name)} else {name.to_owned()}
+}
+
+/// Record `uri` in `category`. If `title` is `None`, the title found
+/// by the analyser is used.
+pub fn make(uri: &str, title: Option<&str>, category: &str, conf: &Config) ->
     Result<(), Error>
 {
     let u = url::Url::parse(uri).map_err(|_| rterr!("Invalid URL: {}", uri))?;
-    let host = u.host_str().ok_or_else(|| rterr!("URL should have a host"))?;
-    let items = if host == "twitter.com" || host == "www.twitter.com"
+    let registry = Registry::fromConfig(conf)?;
+    let entry = registry.choose(&u, conf)?;
+    // The scratch directory is removed when it goes out of scope,
+    // after the record is created.
+    let scratch = tempfile::Builder::new().prefix("cain-").tempdir()
+        .map_err(|e| rterr!("Failed to create scratch directory: {}", e))?;
+
+    // Monolith makes no sense for a URL of a file, so only run it on
+    // actual web pages.
+    let file = if entry.name == "webpage"
     {
-        let client = match conf.twitter_auth.clone()
-        {
-            TwitterAuth::GuestToken =>
-                twitter::Client::new(twitter::GuestToken::new()?)?,
-            TwitterAuth::StaticToken { consumer_key, consumer_secret, access_token,
-                                       access_token_secret } =>
-                twitter::Client::new(twitter::StaticToken {
-                    consumer_key, consumer_secret, access_token,
-                    access_token_secret })?,
-        };
-        client.analyse(uri)?
+        directFile(uri).unwrap_or_else(|e| {
+            warn!("Failed to check the content type of {}: {}", uri, e);
+            None
+        })
+    }
+    else
+    {
+        None
+    };
+    let analysis = if let Some(info) = file
+    {
+        info!("{} is a file of {}. Downloading it directly...", uri,
+              info.content_type.as_deref().unwrap_or(""));
+        let mut analysis = Analysis::new();
+        analysis.addWithInfo(TempItem::Url(uri.to_owned()), info);
+        analysis
     }
     else
     {
-        let downloader = webpage::Downloader::new(
-            conf.single_page_config.download_font,
-            conf.single_page_config.disable_js);
-        downloader.analyse(uri)?
+        info!("Analysing {} with the {} analyser...", uri, entry.name);
+        entry.create(conf)?.analyse(uri, scratch.path())?
     };
 
-    let full_path = conf.root_dir.join(category).join(title);
+    let title = match title.map(str::to_owned).or_else(|| analysis.info.title.clone())
+    {
+        Some(t) if !t.trim().is_empty() => t,
+        _ => return Err(rterr!("No title found for {}. Please specify one.", uri)),
+    };
+    let full_path = conf.root_dir.join(category).join(dirName(&title));
     std::fs::create_dir_all(&full_path).map_err(
         |_| rterr!("Failed to create directory at {:?}", full_path))?;
 
-    createRecord(items, title, uri, &full_path)
+    createRecord(analysis, &title, uri, &full_path)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn dirNames()
+    {
+        assert_eq!(dirName("Attention Is All You Need"), "Attention Is All You Need");
+        assert_eq!(dirName("Input/Output "), "Input_Output");
+        assert_eq!(dirName(".hidden"), "_.hidden");
+        assert_eq!(dirName(&"a".repeat(300)).len(), 100);
+    }
+
+    #[test]
+    fn fileTypes()
+    {
+        assert_eq!(sniffContentType(b"%PDF-1.7\n"), Some("application/pdf"));
+        assert_eq!(sniffContentType(b"\0\0\0\x20ftypisom\0\0\0\0"), Some("video/mp4"));
+        assert_eq!(sniffContentType(b"<!DOCTYPE html>"), None);
+        assert!(isFileType("application/pdf"));
+        assert!(isFileType("Image/PNG"));
+        assert!(!isFileType("text/html"));
+    }
+
+    #[test]
+    fn filenames()
+    {
+        assert_eq!(dispositionFilename("attachment; filename=\"paper.pdf\"").as_deref(),
+                   Some("paper.pdf"));
+        assert_eq!(dispositionFilename(
+            "attachment; filename=\"naive.txt\"; filename*=UTF-8''na%C3%AFve.txt").as_deref(),
+                   Some("naïve.txt"));
+        assert_eq!(dispositionFilename("inline"), None);
+        assert_eq!(urlFilename("https://example.org/files/My%20Paper.pdf?dl=1").as_deref(),
+                   Some("My Paper.pdf"));
+        assert_eq!(urlFilename("https://example.org/"), None);
+    }
 }
@@ -0,0 +1,484 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+
+use log::{info, debug, warn};
+use serde_json::{json, Value};
+use url::Url;
+
+use crate::analyser;
+use crate::analyser::{Analysis, RecordInfo, TempItem, UrlRule};
+use crate::error::Error;
+use crate::html;
+
+// Reddit rejects requests with generic user agents.
+static USER_AGENT: &str = "cain/0.1 (personal web archive)";
+/// `/api/morechildren` takes at most this many comment IDs at a time.
+const MORE_CHILDREN_BATCH: usize = 100;
+/// At most this many requests are made to load the comments that are
+/// not in the first response, so that huge threads do not take
+/// forever. The rest are left as “not loaded”.
+const MAX_MORE_REQUESTS: usize = 50;
+
+/// The URLs handled by the Reddit client.
+pub fn urlRules() -> Result<Vec<UrlRule>, Error>
+{
+    Ok(vec![
+        UrlRule::withPath("reddit.com", r"^/(r/[^/]+/)?comments/")?,
+        UrlRule::withPath("*.reddit.com", r"^/(r/[^/]+/)?comments/")?,
+        UrlRule::withPath("redd.it", r"^/[0-9a-z]+/?$")?,
+    ])
+}
+
+/// Get the URL of the JSON API for the post at `url`.
+fn jsonUrl(url: &str) -> Result<String, Error>
+{
+    let u = Url::parse(url).map_err(|_| rterr!("Invalid URL: {}", url))?;
+    let path = u.path().trim_end_matches('/');
+    let path = if u.host_str() == Some("redd.it")
+    {
+        format!("/comments{}", path)
+    }
+    else
+    {
+        path.to_owned()
+    };
+    Ok(format!("https://www.reddit.com{}.json?raw_json=1&limit=500", path))
+}
+
+fn getJson(url: &str) -> Result<Value, Error>
+{
+    debug!("Getting {}...", url);
+    let res = ureq::get(url).set("User-Agent", USER_AGENT).call()
+        .map_err(|e| rterr!("Failed to get {}: {}", url, e))?;
+    let mut body = String::new();
+    res.into_reader().take(100_000_000).read_to_string(&mut body)
+        .map_err(|e| rterr!("Failed to read response from {}: {}", url, e))?;
+    serde_json::from_str(&body)
+        .map_err(|_| rterr!("Invalid JSON response from {}", url))
+}
+
+fn formatTime(data: &Value) -> Option<String>
+{
+    let stamp = data["created_utc"].as_f64()?;
+    let t = time::OffsetDateTime::from_unix_timestamp(stamp as i64).ok()?;
+    t.format(&time::format_description::well_known::Rfc3339).ok()
+}
+
+/// Find the audio track of a Reddit video. Reddit serves the video
+/// and audio of a video post separately, and the name of the audio
+/// file is not in the API response.
+fn findVideoAudio(fallback_url: &str) -> Option<String>
+{
+    let base = &fallback_url[..fallback_url.rfind('/')? + 1];
+    for name in ["DASH_AUDIO_128.mp4", "DASH_AUDIO_64.mp4", "DASH_audio.mp4",
+                 "audio"]
+    {
+        let u = format!("{}{}", base, name);
+        if ureq::head(&u).set("User-Agent", USER_AGENT).call().is_ok()
+        {
+            return Some(u);
+        }
+    }
+    None
+}
+
+/// Find the media of a post. Return a list of media items and their
+/// roles. This does not include the audio of Reddit videos.
+fn postMedia(post: &Value) -> Vec<(TempItem, &'static str)>
+{
+    // For a cross post, the media is in the original post.
+    if let Some(parent) = post["crosspost_parent_list"].as_array()
+        .and_then(|l| l.first())
+    {
+        return postMedia(parent);
+    }
+
+    let mut result = Vec::new();
+    if let Some(items) = post["gallery_data"]["items"].as_array()
+    {
+        for item in items
+        {
+            let id = match item["media_id"].as_str()
+            {
+                Some(id) => id,
+                None => continue,
+            };
+            let source = &post["media_metadata"][id]["s"];
+            if let Some(u) = source["u"].as_str()
+            {
+                result.push((TempItem::Url(u.to_owned()), "image"));
+            }
+            else if let Some(u) = source["mp4"].as_str()
+                .or_else(|| source["gif"].as_str())
+            {
+                result.push((TempItem::Url(u.to_owned()), "video"));
+            }
+        }
+    }
+    else if let Some(u) = post["secure_media"]["reddit_video"]["fallback_url"]
+        .as_str().or_else(|| post["media"]["reddit_video"]["fallback_url"].as_str())
+    {
+        result.push((TempItem::Url(u.to_owned()), "video"));
+    }
+    else if post["post_hint"].as_str() == Some("image") ||
+        post["domain"].as_str() == Some("i.redd.it")
+    {
+        if let Some(u) = post["url_overridden_by_dest"].as_str()
+            .or_else(|| post["url"].as_str())
+        {
+            result.push((TempItem::Url(u.to_owned()), "image"));
+        }
+    }
+    result
+}
+
+/// Build the comment trees from the flat list of comments `things`
+/// from `/api/morechildren`, and return the ones that reply to
+/// `parent` (a fullname like `t1_abc`).
+fn buildTree(things: Vec<Value>, parent: &str) -> Vec<Value>
+{
+    fn attach(parent: &str, by_parent: &mut HashMap<String, Vec<Value>>) -> Vec<Value>
+    {
+        let mut children = by_parent.remove(parent).unwrap_or_default();
+        for child in &mut children
+        {
+            if child["kind"].as_str() != Some("t1")
+            {
+                continue;
+            }
+            let name = format!("t1_{}", child["data"]["id"].as_str().unwrap_or(""));
+            let replies = attach(&name, by_parent);
+            if !replies.is_empty()
+            {
+                child["data"]["replies"] = json!({"kind": "Listing",
+                                                  "data": {"children": replies}});
+            }
+        }
+        children
+    }
+
+    let mut by_parent: HashMap<String, Vec<Value>> = HashMap::new();
+    for thing in things
+    {
+        let parent_id = thing["data"]["parent_id"].as_str().unwrap_or("").to_owned();
+        by_parent.entry(parent_id).or_default().push(thing);
+    }
+    attach(parent, &mut by_parent)
+}
+
+/// Replace the “more” stubs in the comment `listing` with the
+/// comments they stand for, which are fetched by `getMore` from the
+/// data of the stub. A stub is kept if `getMore` fails.
+fn expandComments<F>(listing: &mut Value, getMore: &mut F)
+    where F: FnMut(&Value) -> Result<Vec<Value>, Error>
+{
+    let children = match listing["data"]["children"].as_array_mut()
+    {
+        Some(c) => c,
+        None => return,
+    };
+    let mut i = 0;
+    while i < children.len()
+    {
+        if children[i]["kind"].as_str() == Some("more")
+        {
+            match getMore(&children[i]["data"])
+            {
+                // The replacement could have stubs too, so it is
+                // expanded from its start.
+                Ok(comments) =>
+                {
+                    children.splice(i..i + 1, comments);
+                },
+                Err(e) =>
+                {
+                    warn!("Failed to load more comments: {}", e);
+                    i += 1;
+                },
+            }
+        }
+        else
+        {
+            expandComments(&mut children[i]["data"]["replies"], getMore);
+            i += 1;
+        }
+    }
+}
+
+/// Get the comments that the “more” stub `stub` in post `post_id`
+/// stands for.
+fn getMoreComments(post_id: &str, stub: &Value, requests: &mut usize) ->
+    Result<Vec<Value>, Error>
+{
+    let ids: Vec<&str> = stub["children"].as_array().into_iter().flatten()
+        .filter_map(|id| id.as_str()).collect();
+    let parent = stub["parent_id"].as_str().unwrap_or("");
+    let batches = if ids.is_empty() {1} else {ids.len().div_ceil(MORE_CHILDREN_BATCH)};
+    if *requests + batches > MAX_MORE_REQUESTS
+    {
+        return Err(rterr!("Too many requests for more comments"));
+    }
+    *requests += batches;
+
+    if ids.is_empty()
+    {
+        // A “continue this thread” link. Its comments are in the
+        // thread of its parent comment.
+        let comment = match parent.strip_prefix("t1_")
+        {
+            Some(c) => c,
+            None => return Ok(Vec::new()),
+        };
+        let data = getJson(&format!(
+            "https://www.reddit.com/comments/{}/_/{}.json?raw_json=1&limit=500",
+            post_id, comment))?;
+        return Ok(data[1]["data"]["children"][0]["data"]["replies"]["data"]["children"]
+                  .as_array().cloned().unwrap_or_default());
+    }
+    let mut things = Vec::new();
+    for batch in ids.chunks(MORE_CHILDREN_BATCH)
+    {
+        let data = getJson(&format!(
+            "https://www.reddit.com/api/morechildren.json?api_type=json&raw_json=1&link_id=t3_{}&children={}",
+            post_id, batch.join(",")))?;
+        things.extend(data["json"]["data"]["things"].as_array().cloned()
+                      .ok_or_else(|| rterr!("Invalid response of more comments"))?);
+    }
+    Ok(buildTree(things, parent))
+}
+
+fn renderComments(listing: &Value, out: &mut String)
+{
+    let children = match listing["data"]["children"].as_array()
+    {
+        Some(c) if !c.is_empty() => c,
+        _ => return,
+    };
+    out.push_str("<ul class=\"comments\">\n");
+    for child in children
+    {
+        let data = &child["data"];
+        match child["kind"].as_str()
+        {
+            Some("t1") =>
+            {
+                let body = match data["body_html"].as_str()
+                {
+                    Some(b) => b.to_owned(),
+                    None => html::escape(data["body"].as_str().unwrap_or("")),
+                };
+                out.push_str(&format!(
+                    "<li>\n<div class=\"meta\"><b>{}</b> · {} points · {}</div>\n{}\n",
+                    html::escape(data["author"].as_str().unwrap_or("[unknown]")),
+                    data["score"].as_i64().unwrap_or(0),
+                    formatTime(data).unwrap_or_default(), body));
+                renderComments(&data["replies"], out);
+                out.push_str("</li>\n");
+            },
+            // Only the stubs that failed to expand are left.
+            Some("more") =>
+            {
+                let count = data["count"].as_i64().unwrap_or(0);
+                if count > 0
+                {
+                    out.push_str(&format!(
+                        "<li class=\"meta\">{} more replies not loaded</li>\n",
+                        count));
+                }
+                else if data["parent_id"].as_str().is_some_and(|p| p.starts_with("t1_"))
+                {
+                    out.push_str("<li class=\"meta\">More replies not loaded</li>\n");
+                }
+            },
+            _ => {},
+        }
+    }
+    out.push_str("</ul>\n");
+}
+
+/// Render the post and its comments into an HTML page.
+fn renderThread(post: &Value, comments: &Value) -> String
+{
+    let title = post["title"].as_str().unwrap_or("");
+    let mut body = format!(
+        "<h1>{}</h1>\n<div class=\"meta\">r/{} · posted by u/{} · {} points · {}</div>\n",
+        html::escape(title),
+        html::escape(post["subreddit"].as_str().unwrap_or("")),
+        html::escape(post["author"].as_str().unwrap_or("[unknown]")),
+        post["score"].as_i64().unwrap_or(0),
+        formatTime(post).unwrap_or_default());
+    if post["is_self"].as_bool() != Some(true)
+    {
+        if let Some(u) = post["url"].as_str()
+        {
+            body.push_str(&format!("<p><a href=\"{0}\">{0}</a></p>\n",
+                                   html::escape(u)));
+        }
+    }
+    if let Some(text) = post["selftext_html"].as_str()
+    {
+        body.push_str(text);
+        body.push('\n');
+    }
+    body.push_str("<h2>Comments</h2>\n");
+    renderComments(comments, &mut body);
+    html::page(title, &body)
+}
+
+pub struct Client {}
+
+impl Client
+{
+    pub fn new() -> Self
+    {
+        Self {}
+    }
+}
+
+impl analyser::ResourceAnalyser for Client
+{
+    fn analyse(&self, url: &str, _scratch: &Path) -> Result<Analysis, Error>
+    {
+        let mut data = getJson(&jsonUrl(url)?)?;
+        let post_id = data[0]["data"]["children"][0]["data"]["id"].as_str()
+            .unwrap_or("").to_owned();
+        let mut requests = 0;
+        expandComments(&mut data[1], &mut |stub| getMoreComments(&post_id, stub, &mut requests));
+        let post = &data[0]["data"]["children"][0]["data"];
+        if !post.is_object()
+        {
+            return Err(rterr!("Failed to find Reddit post at {}", url));
+        }
+
+        let mut analysis = Analysis::new();
+        analysis.info = RecordInfo {
+            title: post["title"].as_str().map(|s| s.to_owned()),
+            authors: post["author"].as_str()
+                .map(|a| vec![format!("u/{}", a)]).unwrap_or_default(),
+            published: formatTime(post),
+        };
+        analysis.addWithRole(TempItem::Html(renderThread(post, &data[1])),
+                             "thread");
+        if let Some(text) = post["selftext"].as_str()
+        {
+            if !text.is_empty()
+            {
+                analysis.addWithRole(TempItem::Text(text.to_owned()), "post");
+            }
+        }
+        for (item, role) in postMedia(post)
+        {
+            let audio = match &item
+            {
+                TempItem::Url(u) if u.starts_with("https://v.redd.it/") =>
+                    findVideoAudio(u),
+                _ => None,
+            };
+            if let TempItem::Url(u) = &item
+            {
+                info!("Found Reddit media at {}.", u);
+            }
+            analysis.addWithRole(item, role);
+            if let Some(audio) = audio
+            {
+                analysis.addWithRole(TempItem::Url(audio), "audio");
+            }
+        }
+        Ok(analysis)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn urls() -> Result<()>
+    {
+        assert_eq!(jsonUrl("https://www.reddit.com/r/rust/comments/abc123/some_title/")?,
+                   "https://www.reddit.com/r/rust/comments/abc123/some_title.json?raw_json=1&limit=500");
+        assert_eq!(jsonUrl("https://redd.it/abc123")?,
+                   "https://www.reddit.com/comments/abc123.json?raw_json=1&limit=500");
+        Ok(())
+    }
+
+    #[test]
+    fn media() -> Result<()>
+    {
+        let post: Value = serde_json::from_str(r#"{
+            "gallery_data": {"items": [{"media_id": "a"}, {"media_id": "b"}]},
+            "media_metadata": {
+                "a": {"s": {"u": "https://i.redd.it/a.jpg"}},
+                "b": {"s": {"gif": "https://i.redd.it/b.gif",
+                            "mp4": "https://i.redd.it/b.mp4"}}}}"#)?;
+        assert_eq!(postMedia(&post), vec![
+            (TempItem::Url(String::from("https://i.redd.it/a.jpg")), "image"),
+            (TempItem::Url(String::from("https://i.redd.it/b.mp4")), "video")]);
+
+        let post: Value = serde_json::from_str(r#"{
+            "crosspost_parent_list": [{"secure_media": {"reddit_video": {
+                "fallback_url": "https://v.redd.it/xyz/DASH_720.mp4?source=fallback"}}}]}"#)?;
+        assert_eq!(postMedia(&post), vec![
+            (TempItem::Url(String::from("https://v.redd.it/xyz/DASH_720.mp4?source=fallback")),
+             "video")]);
+        Ok(())
+    }
+
+    #[test]
+    fn comments()
+    {
+        let listing: Value = serde_json::from_str(r#"{"data": {"children": [
+            {"kind": "t1", "data": {"author": "a", "score": 3,
+                                    "body_html": "<p>Top</p>",
+                                    "replies": {"data": {"children": [
+                {"kind": "t1", "data": {"author": "b<", "body": "Reply",
+                                        "replies": ""}}]}}}},
+            {"kind": "more", "data": {"count": 5}}]}}"#).unwrap();
+        let mut out = String::new();
+        renderComments(&listing, &mut out);
+        assert!(out.contains("<p>Top</p>"));
+        assert!(out.contains("<b>b&lt;</b>"));
+        assert!(out.contains("5 more replies"));
+        assert_eq!(out.matches("<ul").count(), 2);
+    }
+
+    #[test]
+    fn moreComments() -> Result<()>
+    {
+        let mut listing: Value = serde_json::from_str(r#"{"data": {"children": [
+            {"kind": "t1", "data": {"id": "a", "body": "A", "replies": {"data": {"children": [
+                {"kind": "more", "data": {"count": 0, "id": "_", "parent_id": "t1_a",
+                                          "children": []}}]}}}},
+            {"kind": "more", "data": {"count": 3, "parent_id": "t3_p",
+                                      "children": ["b", "c"]}}]}}"#)?;
+        let mut stubs = Vec::new();
+        expandComments(&mut listing, &mut |stub: &Value| {
+            stubs.push(stub["parent_id"].clone());
+            if stub["parent_id"] == "t1_a"
+            {
+                return Err(rterr!("Not found"));
+            }
+            // As from /api/morechildren: flat, with a reply to “b”.
+            Ok(buildTree(serde_json::from_str(r#"[
+                {"kind": "t1", "data": {"id": "b", "parent_id": "t3_p", "body": "B"}},
+                {"kind": "t1", "data": {"id": "d", "parent_id": "t1_b", "body": "D"}},
+                {"kind": "t1", "data": {"id": "c", "parent_id": "t3_p", "body": "C"}}]"#)
+                          .unwrap(), "t3_p"))
+        });
+        assert_eq!(stubs, vec!["t1_a", "t3_p"]);
+        let children = listing["data"]["children"].as_array().unwrap();
+        assert_eq!(children.len(), 3);
+        assert_eq!(children[1]["data"]["replies"]["data"]["children"][0]["data"]["body"], "D");
+
+        let mut out = String::new();
+        renderComments(&listing, &mut out);
+        assert!(out.contains("B\n<ul"));
+        assert!(out.contains("D\n</li>"));
+        assert!(out.contains("C\n</li>"));
+        assert!(out.contains("More replies not loaded"));
+        Ok(())
+    }
+}
@@ -0,0 +1,267 @@
+use url::Url;
+
+use crate::analyser::{ResourceAnalyser, UrlRule, globMatch};
+use crate::arxiv;
+use crate::bluesky;
+use crate::config::{Config, SinglePageConfig, WebPageBackend};
+use crate::error::Error;
+use crate::discussion;
+use crate::external;
+use crate::github;
+use crate::mastodon;
+use crate::reddit;
+use crate::twitter;
+use crate::video;
+use crate::webpage;
+
+type Factory = Box<dyn Fn(&Config) -> Result<Box<dyn ResourceAnalyser>, Error>>;
+
+fn webpageDownloader(conf: &Config) -> Box<dyn ResourceAnalyser>
+{
+    Box::new(webpage::Downloader::new(conf.single_page_config.clone()))
+}
+
+/// The web page downloader that always renders the page in Chromium.
+fn chromiumDownloader(conf: &Config) -> Box<dyn ResourceAnalyser>
+{
+    Box::new(webpage::Downloader::new(SinglePageConfig {
+        backend: WebPageBackend::Chromium,
+        ..conf.single_page_config.clone()
+    }))
+}
+
+/// A registered analyser. The analyser itself is only constructed
+/// when it is actually needed, because some analysers (e.g. Twitter)
+/// need network access to initialize.
+pub struct Entry
+{
+    pub name: String,
+    /// When multiple analysers can handle an URL, the one with the
+    /// highest priority is chosen.
+    pub priority: i32,
+    pub rules: Vec<UrlRule>,
+    factory: Factory,
+}
+
+impl Entry
+{
+    pub fn new<F>(name: &str, priority: i32, rules: Vec<UrlRule>, factory: F)
+                  -> Self
+        where F: Fn(&Config) -> Result<Box<dyn ResourceAnalyser>, Error> + 'static
+    {
+        Self {
+            name: name.to_owned(),
+            priority,
+            rules,
+            factory: Box::new(factory),
+        }
+    }
+
+    pub fn matches(&self, url: &Url) -> bool
+    {
+        self.rules.iter().any(|rule| rule.matches(url))
+    }
+
+    pub fn create(&self, conf: &Config) -> Result<Box<dyn ResourceAnalyser>, Error>
+    {
+        (self.factory)(conf)
+    }
+}
+
+pub struct Registry
+{
+    entries: Vec<Entry>,
+}
+
+impl Registry
+{
+    pub fn new() -> Self
+    {
+        Self { entries: Vec::new() }
+    }
+
+    /// Create a registry with all the built-in analysers, and the
+    /// external analysers defined in `conf`.
+    pub fn fromConfig(conf: &Config) -> Result<Self, Error>
+    {
+        let mut registry = Self::builtin()?;
+        for plugin_conf in &conf.external_analysers
+        {
+            if registry.find(&plugin_conf.name).is_some()
+            {
+                return Err(rterr!("Duplicated analyser name: {}",
+                                  plugin_conf.name));
+            }
+            let rules = external::urlRules(plugin_conf)?;
+            let factory_conf = plugin_conf.clone();
+            registry.register(Entry::new(
+                &plugin_conf.name, plugin_conf.priority, rules,
+                move |_| Ok(Box::new(external::Plugin::new(factory_conf.clone())))));
+        }
+        Ok(registry)
+    }
+
+    /// Create a registry with all the built-in analysers.
+    pub fn builtin() -> Result<Self, Error>
+    {
+        let mut registry = Self::new();
+        registry.register(Entry::new(
+            "twitter", 10, twitter::urlRules()?,
+            |conf| Ok(Box::new(twitter::Client::fromConfig(conf)?))));
+        registry.register(Entry::new(
+            "reddit", 10, reddit::urlRules()?,
+            |_| Ok(Box::new(reddit::Client::new()))));
+        registry.register(Entry::new(
+            "bluesky", 10, bluesky::urlRules()?,
+            |_| Ok(Box::new(bluesky::Client::new()))));
+        registry.register(Entry::new(
+            "discussion", 10, discussion::urlRules()?,
+            |conf| Ok(Box::new(discussion::Client::new(webpageDownloader(conf))))));
+        registry.register(Entry::new(
+            "github", 10, github::urlRules()?,
+            |conf| Ok(Box::new(github::Client::new(conf.github_config.clone(),
+                                                   webpageDownloader(conf))))));
+        registry.register(Entry::new(
+            "arxiv", 10, arxiv::urlRules()?,
+            |_| Ok(Box::new(arxiv::Client::new()))));
+        registry.register(Entry::new(
+            "video", 10, video::urlRules()?,
+            |conf| Ok(Box::new(video::Downloader::new(conf.video_config.clone())))));
+        // Mastodon servers can only be recognized by the URL path,
+        // which is less certain than the rules above.
+        registry.register(Entry::new(
+            "mastodon", 5, mastodon::urlRules()?,
+            |conf| Ok(Box::new(mastodon::Client::new(webpageDownloader(conf))))));
+        registry.register(Entry::new(
+            "webpage", 0, vec![UrlRule::new("*")],
+            |conf| Ok(webpageDownloader(conf))));
+        // Rendering in a browser is slow, so it is only used for the
+        // hosts it is explicitly chosen for in the overrides.
+        registry.register(Entry::new(
+            "chromium", 0, Vec::new(),
+            |conf| Ok(chromiumDownloader(conf))));
+        Ok(registry)
+    }
+
+    pub fn register(&mut self, entry: Entry)
+    {
+        self.entries.push(entry);
+    }
+
+    pub fn entries(&self) -> &[Entry]
+    {
+        &self.entries
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Entry>
+    {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    /// Choose the analyser to handle `url`. Overrides in the config
+    /// take precedence over the rules of the analysers. If multiple
+    /// overrides match the host, the longest (and presumably the most
+    /// specific) pattern wins.
+    pub fn choose(&self, url: &Url, conf: &Config) -> Result<&Entry, Error>
+    {
+        let host = url.host_str().ok_or_else(|| rterr!("URL should have a host"))?;
+        let forced = conf.analyser_overrides.iter()
+            .filter(|(pattern, _)| globMatch(pattern, host))
+            .max_by_key(|(pattern, _)| pattern.len());
+        if let Some((pattern, name)) = forced
+        {
+            return self.find(name).ok_or_else(
+                || rterr!("Unknown analyser {} in override for {}", name, pattern));
+        }
+
+        // Find the matching entry with the highest priority. Among
+        // entries with the same priority, the one registered first
+        // wins.
+        let mut chosen: Option<&Entry> = None;
+        for entry in self.entries.iter().filter(|entry| entry.matches(url))
+        {
+            if chosen.map(|c| entry.priority > c.priority).unwrap_or(true)
+            {
+                chosen = Some(entry);
+            }
+        }
+        chosen.ok_or_else(|| rterr!("No analyser can handle {}", url))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use anyhow::Result;
+    use crate::config::ExternalAnalyserConfig;
+
+    fn chosenName(registry: &Registry, url: &str, conf: &Config) -> Result<String>
+    {
+        Ok(registry.choose(&Url::parse(url)?, conf)?.name.clone())
+    }
+
+    #[test]
+    fn choose() -> Result<()>
+    {
+        let mut conf = Config::default();
+        conf.external_analysers.push(ExternalAnalyserConfig {
+            name: String::from("scraper"),
+            command: String::from("true"),
+            args: Vec::new(),
+            hosts: vec![String::from("*.example.org")],
+            path: Some(String::from("^/articles/")),
+            priority: 20,
+            timeout: 1,
+        });
+        let registry = Registry::fromConfig(&conf)?;
+        assert_eq!(chosenName(&registry, "https://twitter.com/MetroWind/status/1595694065353248768", &conf)?,
+                   "twitter");
+        assert_eq!(chosenName(&registry, "https://example.org/", &conf)?,
+                   "webpage");
+        assert_eq!(chosenName(&registry, "https://x.com/MetroWind/status/1595694065353248768", &conf)?,
+                   "twitter");
+        assert_eq!(chosenName(&registry, "https://twitter.com/MetroWind", &conf)?,
+                   "webpage");
+        assert_eq!(chosenName(&registry, "https://www.youtube.com/watch?v=dQw4w9WgXcQ", &conf)?,
+                   "video");
+        assert_eq!(chosenName(&registry, "https://www.youtube.com/", &conf)?,
+                   "webpage");
+        assert_eq!(chosenName(&registry, "https://old.reddit.com/r/rust/comments/abc123/title/", &conf)?,
+                   "reddit");
+        assert_eq!(chosenName(&registry, "https://www.example.org/", &conf)?,
+                   "webpage");
+        assert_eq!(chosenName(&registry, "https://mastodon.social/@Gargron/109318821117356215", &conf)?,
+                   "mastodon");
+        assert_eq!(chosenName(&registry, "https://bsky.app/profile/jay.bsky.team/post/3juzlwllznd24", &conf)?,
+                   "bluesky");
+        assert_eq!(chosenName(&registry, "https://news.ycombinator.com/item?id=8863", &conf)?,
+                   "discussion");
+        assert_eq!(chosenName(&registry, "https://github.com/MetroWind/cain", &conf)?,
+                   "github");
+        assert_eq!(chosenName(&registry, "https://arxiv.org/abs/1706.03762", &conf)?,
+                   "arxiv");
+        assert_eq!(chosenName(&registry, "https://news.ycombinator.com/news", &conf)?,
+                   "webpage");
+        assert_eq!(chosenName(&registry, "https://www.example.org/articles/1", &conf)?,
+                   "scraper");
+
+        conf.analyser_overrides.insert("*twitter.com".to_owned(),
+                                       "webpage".to_owned());
+        assert_eq!(chosenName(&registry, "https://twitter.com/MetroWind/status/1595694065353248768", &conf)?,
+                   "webpage");
+
+        conf.analyser_overrides.insert("*.spa.example.com".to_owned(),
+                                       "chromium".to_owned());
+        assert_eq!(chosenName(&registry, "https://app.spa.example.com/", &conf)?,
+                   "chromium");
+        assert_eq!(chosenName(&registry, "https://example.com/", &conf)?,
+                   "webpage");
+
+        conf.analyser_overrides.insert("example.org".to_owned(),
+                                       "nonexistent".to_owned());
+        assert!(registry.choose(&Url::parse("https://example.org/")?, &conf)
+                .is_err());
+        Ok(())
+    }
+}
@@ -1,6 +1,9 @@
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::io::Read;
+use std::path::Path;
 use std::str;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use ureq;
 use serde_json;
@@ -10,14 +13,22 @@ use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
 use hmac::Mac;
 
 use crate::error::Error;
+use crate::html;
 use crate::runtime_config;
 use crate::analyser;
-use crate::analyser::TempItem;
+use crate::analyser::{Analysis, Resource, ResourceInfo, TempItem, UrlRule};
+use crate::config::{Config, TwitterAuth};
 
 static GUEST_TOKEN_KEY: &str = "twitter_guest_token";
+static APP_TOKEN_KEY: &str = "twitter_app_token";
 static GUEST_AUTH: &str = "Bearer AAAAAAAAAAAAAAAAAAAAANRILgAAAAAAnNwIzUejRCOuH5E6I8xnZz4puTs%3D1Zv7ttfk8LF81IUq16cHjhLTvJu4FA33AGWWjCpTnA";
 static AUTH_HEADER_KEY: &str = "Authorization";
 static GUEST_TOKEN_HEADER_KEY: &str = "X-guest-token";
+/// How many times to retry a request after being rate limited.
+const RATE_LIMIT_RETRIES: u32 = 3;
+/// Rate limit windows are 15 minutes. Never wait longer than this
+/// even if the server says otherwise.
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(16 * 60);
 
 fn newGuestToken() -> Result<String, Error>
 {
@@ -33,6 +44,29 @@ fn newGuestToken() -> Result<String, Error>
        .ok_or_else(|| rterr!("Invalid guest token response"))?.to_owned())
 }
 
+/// Get an app-only bearer token with the client credentials grant. See
+/// https://developer.twitter.com/en/docs/authentication/oauth-2-0/application-only
+fn newAppToken(consumer_key: &str, consumer_secret: &str) -> Result<String, Error>
+{
+    let credentials = base64::encode(format!(
+        "{}:{}", percentEncode(consumer_key), percentEncode(consumer_secret)));
+    let res = ureq::post("https://api.twitter.com/oauth2/token")
+        .set(AUTH_HEADER_KEY, &format!("Basic {}", credentials))
+        .set("Content-Type", "application/x-www-form-urlencoded;charset=UTF-8")
+        .send_string("grant_type=client_credentials")
+        .map_err(|e| rterr!("Failed to get app token: {}", e))?
+        .into_string().map_err(
+            |_| rterr!("Failed to encode app token response"))?;
+    let data: serde_json::Value = res.parse()
+        .map_err(|_| rterr!("Failed to serialize app token response"))?;
+    if data["token_type"].as_str() != Some("bearer")
+    {
+        return Err(rterr!("Invalid app token response: {}", res));
+    }
+    Ok(data["access_token"].as_str()
+       .ok_or_else(|| rterr!("Invalid app token response"))?.to_owned())
+}
+
 fn percentEncode(s: &str) -> String
 {
     utf8_percent_encode(s, NON_ALPHANUMERIC).to_string()
@@ -94,6 +128,57 @@ impl TokenManager for GuestToken
     fn reauthenticate(&self) -> bool {true}
 }
 
+/// App-only authentication. The bearer token is acquired from the
+/// consumer key and secret.
+pub struct AppToken
+{
+    consumer_key: String,
+    consumer_secret: String,
+    token: Cell<String>,
+}
+
+impl AppToken
+{
+    pub fn new(consumer_key: String, consumer_secret: String) ->
+        Result<Self, Error>
+    {
+        let t = Self { consumer_key, consumer_secret, token: Cell::default() };
+        if let Some(token) = runtime_config::get(APP_TOKEN_KEY)?
+        {
+            t.token.set(token);
+        }
+        else
+        {
+            t.authenticate()?;
+        }
+        Ok(t)
+    }
+}
+
+impl TokenManager for AppToken
+{
+    fn authenticate(&self) -> Result<(), Error>
+    {
+        let token = newAppToken(&self.consumer_key, &self.consumer_secret)?;
+        if let Err(e) = runtime_config::set(APP_TOKEN_KEY, &token)
+        {
+            warn!("Failed to set runtime config: {}", e);
+        }
+        self.token.set(token);
+        Ok(())
+    }
+
+    fn decorated(&self, req: ureq::Request) -> Result<ureq::Request, Error>
+    {
+        let t = self.token.take();
+        let r = req.set(AUTH_HEADER_KEY, &format!("Bearer {}", t));
+        self.token.set(t);
+        Ok(r)
+    }
+
+    fn reauthenticate(&self) -> bool {true}
+}
+
 pub struct StaticToken
 {
     pub consumer_key: String,
@@ -170,9 +255,82 @@ impl TokenManager for StaticToken
     }
 }
 
+/// An OAuth 2.0 access token in user context, from the authorization
+/// code flow with PKCE. Some endpoints, like bookmarks, only accept
+/// this. It is not refreshed.
+pub struct UserToken
+{
+    pub access_token: String,
+}
+
+impl TokenManager for UserToken
+{
+    fn authenticate(&self) -> Result<(), Error>
+    {
+        Ok(())
+    }
+
+    fn decorated(&self, req: ureq::Request) -> Result<ureq::Request, Error>
+    {
+        Ok(req.set(AUTH_HEADER_KEY, &format!("Bearer {}", self.access_token)))
+    }
+}
+
+/// The rate limit status of an API endpoint, from the
+/// x-rate-limit-* headers of its last response.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimit
+{
+    /// Number of requests left in the current window.
+    pub remaining: u64,
+    /// Unix time in seconds when the current window resets.
+    pub reset: u64,
+}
+
+impl RateLimit
+{
+    fn fromResponse(res: &ureq::Response) -> Option<Self>
+    {
+        Some(Self {
+            remaining: res.header("x-rate-limit-remaining")?.trim().parse().ok()?,
+            reset: res.header("x-rate-limit-reset")?.trim().parse().ok()?,
+        })
+    }
+
+    /// How long to wait at unix time `now` before the next request
+    /// can be made.
+    pub fn wait(&self, now: u64) -> Duration
+    {
+        if self.remaining > 0 || self.reset <= now
+        {
+            Duration::ZERO
+        }
+        else
+        {
+            // One more second for clock differences.
+            Duration::from_secs(self.reset - now + 1).min(MAX_RATE_LIMIT_WAIT)
+        }
+    }
+}
+
+fn unixNow() -> u64
+{
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Rate limits are per endpoint, which is the URL without the query.
+fn endpointOf(url: &str) -> &str
+{
+    url.split('?').next().unwrap()
+}
+
 pub struct Client
 {
     token: Box<dyn TokenManager>,
+    /// Whether to archive the whole thread by the author of the tweet.
+    thread: bool,
+    rate_limits: RefCell<HashMap<String, RateLimit>>,
 }
 
 impl Client
@@ -180,10 +338,67 @@ impl Client
     pub fn new<T>(t: T) -> Result<Self, Error>
         where T: TokenManager + 'static
     {
-        Ok(Self { token: Box::new(t) })
+        Ok(Self { token: Box::new(t), thread: false,
+                  rate_limits: RefCell::new(HashMap::new()) })
     }
 
-    fn getTweet(&self, id: &str) -> Result<serde_json::Value, Error>
+    pub fn setThread(&mut self, thread: bool)
+    {
+        self.thread = thread;
+    }
+
+    /// Create a client with the authentication method in `conf`.
+    pub fn fromConfig(conf: &Config) -> Result<Self, Error>
+    {
+        let mut client = match conf.twitter_auth.clone()
+        {
+            TwitterAuth::GuestToken => Self::new(GuestToken::new()?),
+            TwitterAuth::StaticToken { consumer_key, consumer_secret, access_token,
+                                       access_token_secret } =>
+                Self::new(StaticToken {
+                    consumer_key, consumer_secret, access_token,
+                    access_token_secret }),
+            TwitterAuth::AppToken { consumer_key, consumer_secret } =>
+                Self::new(AppToken::new(consumer_key, consumer_secret)?),
+        }?;
+        client.setThread(conf.twitter_config.thread);
+        Ok(client)
+    }
+
+    /// The rate limit status of the API endpoint of `url`, if it has
+    /// been called.
+    pub fn rateLimit(&self, url: &str) -> Option<RateLimit>
+    {
+        self.rate_limits.borrow().get(endpointOf(url)).copied()
+    }
+
+    fn updateRateLimit(&self, url: &str, res: &ureq::Response)
+    {
+        if let Some(limit) = RateLimit::fromResponse(res)
+        {
+            debug!("Rate limit of {}: {:?}", endpointOf(url), limit);
+            self.rate_limits.borrow_mut().insert(endpointOf(url).to_owned(), limit);
+        }
+    }
+
+    /// Wait until the endpoint of `url` can be called again, if its
+    /// rate limit is used up.
+    fn waitForRateLimit(&self, url: &str)
+    {
+        if let Some(limit) = self.rateLimit(url)
+        {
+            let wait = limit.wait(unixNow());
+            if !wait.is_zero()
+            {
+                warn!("Rate limit of {} is used up. Waiting {} seconds...",
+                      endpointOf(url), wait.as_secs());
+                std::thread::sleep(wait);
+            }
+        }
+    }
+
+    /// Call the API at `url`, and return the JSON response.
+    fn getJson(&self, url: &str) -> Result<serde_json::Value, Error>
     {
         // Twitter error response does not have Content-Length, and by
         // default ureq will wait for the server to close socket when
@@ -192,118 +407,609 @@ impl Client
             .timeout_read(std::time::Duration::from_secs(10))
             .build();
 
-        let req = self.token.decorated(agent.get(&format!(
-            "https://api.twitter.com/1.1/statuses/show.json?id={}", id)))?;
-        debug!("Sending request...");
-        let res = match req.clone().call()
+        let mut retries = 0;
+        let mut refreshed = false;
+        let res = loop
         {
-            Ok(res) => res,
-            Err(ureq::Error::Status(401, res)) |
-            Err(ureq::Error::Status(403, res)) =>
+            self.waitForRateLimit(url);
+            // Sign every attempt, because an OAuth 1.0a signature has a
+            // time stamp and a nonce, and cannot be sent again.
+            let req = self.token.decorated(agent.get(url))?;
+            debug!("Sending request...");
+            break match req.call()
             {
-                let mut bytes: Vec<u8> = Vec::with_capacity(1024);
-                // This could timeout.
-                debug!("Reading response...");
-                let _ = res.into_reader().take(10_000_000)
-                    .read_to_end(&mut bytes);
-                debug!("Done.");
-                let payload = str::from_utf8(&bytes).unwrap();
-
-                if self.token.reauthenticate()
+                Ok(res) => res,
+                Err(ureq::Error::Status(429, res)) if retries < RATE_LIMIT_RETRIES =>
                 {
-                    self.token.authenticate()?;
-                    self.token.decorated(req)?.call()
-                        .map_err(|_| rterr!(
-                            "Failed to get tweet with refreshed token"))?
-                }
-                else
+                    retries += 1;
+                    // Back off for a while if the headers are missing or
+                    // wrong.
+                    let limit = RateLimit::fromResponse(&res)
+                        .filter(|l| l.reset > unixNow())
+                        .unwrap_or(RateLimit {
+                            remaining: 0,
+                            reset: unixNow() + 60 * u64::from(retries),
+                        });
+                    let limit = RateLimit { remaining: 0, ..limit };
+                    warn!("Rate limited by {}. Retrying after {} seconds...",
+                          endpointOf(url), limit.wait(unixNow()).as_secs());
+                    self.rate_limits.borrow_mut().insert(endpointOf(url).to_owned(), limit);
+                    continue;
+                },
+                Err(ureq::Error::Status(401, res)) |
+                Err(ureq::Error::Status(403, res)) =>
                 {
-                    return Err(rterr!("Invalid token: {}", payload));
-                }
-            },
-            Err(ureq::Error::Status(code, res)) =>
-            {
-                let mut bytes: Vec<u8> = Vec::with_capacity(1024);
-                // This could timeout.
-                let _ = res.into_reader().take(10_000_000)
-                    .read_to_end(&mut bytes);
-                let payload = str::from_utf8(&bytes).unwrap();
-                let err = rterr!(
-                    "Failed to get tweet with code {} and error: {}",
-                    code, payload);
-                return Err(err);
-            },
-            Err(_) =>
-            {
-                return Err(rterr!("Failed to get tweet"));
-            },
+                    let mut bytes: Vec<u8> = Vec::with_capacity(1024);
+                    // This could timeout.
+                    debug!("Reading response...");
+                    let _ = res.into_reader().take(10_000_000)
+                        .read_to_end(&mut bytes);
+                    debug!("Done.");
+                    let payload = str::from_utf8(&bytes).unwrap();
+
+                    if refreshed
+                    {
+                        return Err(rterr!("Failed to call {} with refreshed token: {}",
+                                          url, payload));
+                    }
+                    else if self.token.reauthenticate()
+                    {
+                        self.token.authenticate()?;
+                        refreshed = true;
+                        continue;
+                    }
+                    else
+                    {
+                        return Err(rterr!("Invalid token: {}", payload));
+                    }
+                },
+                Err(ureq::Error::Status(code, res)) =>
+                {
+                    let mut bytes: Vec<u8> = Vec::with_capacity(1024);
+                    // This could timeout.
+                    let _ = res.into_reader().take(10_000_000)
+                        .read_to_end(&mut bytes);
+                    let payload = str::from_utf8(&bytes).unwrap();
+                    let err = rterr!(
+                        "Failed to call {} with code {} and error: {}",
+                        url, code, payload);
+                    return Err(err);
+                },
+                Err(_) =>
+                {
+                    return Err(rterr!("Failed to call {}", url));
+                },
+            };
         };
+        self.updateRateLimit(url, &res);
 
         let body = res.into_string().map_err(
-                |_| rterr!("Failed to encode tweet response"))?;
+                |_| rterr!("Failed to encode response from {}", url))?;
         let data: serde_json::Value = body.parse()
-            .map_err(|_| rterr!("Failed to serialize tweet response"))?;
+            .map_err(|_| rterr!("Failed to serialize response from {}", url))?;
 
         Ok(data)
     }
+
+    fn getTweet(&self, id: &str) -> Result<serde_json::Value, Error>
+    {
+        self.getJson(&format!(
+            "https://api.twitter.com/1.1/statuses/show.json?id={}&tweet_mode=extended",
+            id))
+    }
+
+    /// Get a page of tweets from the v1.1 timeline API at `url`, newer
+    /// than tweet `since_id` and not newer than tweet `max_id`.
+    fn getTimeline(&self, url: &str, since_id: Option<&str>,
+                   max_id: Option<u64>) -> Result<Vec<serde_json::Value>, Error>
+    {
+        let mut url = format!("{}&count=200&tweet_mode=extended", url);
+        if let Some(id) = since_id
+        {
+            url.push_str(&format!("&since_id={}", id));
+        }
+        if let Some(id) = max_id
+        {
+            url.push_str(&format!("&max_id={}", id));
+        }
+        self.getJson(&url)?.as_array().cloned()
+            .ok_or_else(|| rterr!("Invalid timeline from {}", url))
+    }
+
+    /// Get a page of the tweets of `handle`, including retweets. See
+    /// `getTimeline()` for the IDs.
+    pub fn getUserTweets(&self, handle: &str, since_id: Option<&str>,
+                         max_id: Option<u64>) ->
+        Result<Vec<serde_json::Value>, Error>
+    {
+        self.getTimeline(&format!(
+            "https://api.twitter.com/1.1/statuses/user_timeline.json?screen_name={}&include_rts=true",
+            percentEncode(handle)), since_id, max_id)
+    }
+
+    /// Get the ID of the authenticated user.
+    pub fn getSelfId(&self) -> Result<String, Error>
+    {
+        let data = self.getJson("https://api.twitter.com/2/users/me")?;
+        data["data"]["id"].as_str().map(|s| s.to_owned())
+            .ok_or_else(|| rterr!("Failed to get the authenticated user"))
+    }
+
+    /// Get the ID of the user `handle`.
+    pub fn getUserId(&self, handle: &str) -> Result<String, Error>
+    {
+        let data = self.getJson(&format!(
+            "https://api.twitter.com/2/users/by/username/{}", percentEncode(handle)))?;
+        data["data"]["id"].as_str().map(|s| s.to_owned())
+            .ok_or_else(|| rterr!("User {} not found", handle))
+    }
+
+    /// Get a page of tweet IDs from the v2 API at `url`. Return the IDs
+    /// and the token of the next page.
+    fn getIdPage(&self, url: &str, page_token: Option<&str>) ->
+        Result<(Vec<String>, Option<String>), Error>
+    {
+        let mut url = format!("{}?max_results=100", url);
+        if let Some(t) = page_token
+        {
+            url.push_str(&format!("&pagination_token={}", percentEncode(t)));
+        }
+        let data = self.getJson(&url)?;
+        let ids = data["data"].as_array().into_iter().flatten()
+            .filter_map(|t| t["id"].as_str().map(|s| s.to_owned())).collect();
+        Ok((ids, data["meta"]["next_token"].as_str().map(|s| s.to_owned())))
+    }
+
+    /// Get a page of the IDs of tweets liked by user `user_id`, most
+    /// recently liked first. See `getIdPage()` for the result.
+    pub fn getLikedIds(&self, user_id: &str, page_token: Option<&str>) ->
+        Result<(Vec<String>, Option<String>), Error>
+    {
+        self.getIdPage(&format!("https://api.twitter.com/2/users/{}/liked_tweets", user_id),
+                       page_token)
+    }
+
+    /// Get a page of the IDs of tweets bookmarked by user `user_id`,
+    /// most recently bookmarked first. See `getIdPage()` for the
+    /// result. This needs a `UserToken`.
+    pub fn getBookmarkIds(&self, user_id: &str, page_token: Option<&str>) ->
+        Result<(Vec<String>, Option<String>), Error>
+    {
+        self.getIdPage(&format!("https://api.twitter.com/2/users/{}/bookmarks", user_id),
+                       page_token)
+    }
+
+    /// Get the tweets with `ids`, at most 100 of them. Deleted tweets
+    /// are skipped, and the order is not kept.
+    pub fn lookupTweets(&self, ids: &[String]) ->
+        Result<Vec<serde_json::Value>, Error>
+    {
+        let url = format!(
+            "https://api.twitter.com/1.1/statuses/lookup.json?id={}&tweet_mode=extended",
+            ids.join(","));
+        self.getJson(&url)?.as_array().cloned()
+            .ok_or_else(|| rterr!("Invalid response from {}", url))
+    }
+
+    /// Search for the recent replies of `handle` to itself, newer than
+    /// tweet `since_id`. The search API only covers about a week of
+    /// tweets.
+    fn getSelfReplies(&self, handle: &str, since_id: &str) ->
+        Result<Vec<serde_json::Value>, Error>
+    {
+        let mut replies = Vec::new();
+        let mut query = format!(
+            "?q={}&since_id={}&count=100&result_type=recent&tweet_mode=extended",
+            percentEncode(&format!("from:{} to:{}", handle, handle)), since_id);
+        // Don't go crazy on long threads.
+        for _ in 0..10
+        {
+            let data = self.getJson(&format!(
+                "https://api.twitter.com/1.1/search/tweets.json{}", query))?;
+            if let Some(statuses) = data["statuses"].as_array()
+            {
+                replies.extend(statuses.iter().cloned());
+            }
+            match data["search_metadata"]["next_results"].as_str()
+            {
+                Some(q) => query = q.to_owned(),
+                None => break,
+            }
+        }
+        Ok(replies)
+    }
+
+    /// Get the thread `tweet` is in, as written by its author, in
+    /// order.
+    fn getThread(&self, tweet: serde_json::Value) ->
+        Result<Vec<serde_json::Value>, Error>
+    {
+        let author = tweet["user"]["id_str"].as_str()
+            .ok_or_else(|| rterr!("Failed to get tweet author"))?.to_owned();
+        let handle = tweet["user"]["screen_name"].as_str()
+            .ok_or_else(|| rterr!("Failed to get tweet author"))?.to_owned();
+        let mut thread = vec![tweet];
+
+        // Walk upward through the replies.
+        while thread[0]["in_reply_to_user_id_str"].as_str() == Some(&author)
+        {
+            let parent_id = match thread[0]["in_reply_to_status_id_str"].as_str()
+            {
+                Some(id) => id.to_owned(),
+                None => break,
+            };
+            debug!("Getting parent tweet {}...", parent_id);
+            match self.getTweet(&parent_id)
+            {
+                Ok(parent) => thread.insert(0, parent),
+                Err(e) =>
+                {
+                    warn!("Failed to get tweet {} in thread: {}", parent_id, e);
+                    break;
+                },
+            }
+        }
+
+        // Walk downward. There is no API to get the replies of a
+        // tweet, so we search for them.
+        let last_id = thread.last().unwrap()["id_str"].as_str()
+            .ok_or_else(|| rterr!("Failed to get tweet ID"))?.to_owned();
+        let replies = self.getSelfReplies(&handle, &last_id)
+            .unwrap_or_else(|e| {
+                warn!("Failed to search for replies in thread: {}", e);
+                Vec::new()
+            });
+        thread.extend(chainReplies(&last_id, &author, &replies));
+        Ok(thread)
+    }
+}
+
+/// Find the chain of replies by `author` starting from tweet
+/// `start_id` in `replies`.
+fn chainReplies(start_id: &str, author: &str, replies: &[serde_json::Value]) ->
+    Vec<serde_json::Value>
+{
+    let mut chain: Vec<serde_json::Value> = Vec::new();
+    let mut last_id = start_id.to_owned();
+    while let Some(reply) = replies.iter().find(|r| {
+        r["in_reply_to_status_id_str"].as_str() == Some(&last_id) &&
+            r["user"]["id_str"].as_str() == Some(author)
+    })
+    {
+        last_id = match reply["id_str"].as_str()
+        {
+            Some(id) => id.to_owned(),
+            None => break,
+        };
+        chain.push(reply.clone());
+    }
+    chain
+}
+
+static TWITTER_HOSTS: &[&str] = &[
+    "twitter.com", "www.twitter.com", "mobile.twitter.com",
+    "x.com", "www.x.com", "mobile.x.com",
+    "fxtwitter.com", "www.fxtwitter.com", "fixupx.com",
+    "vxtwitter.com", "www.vxtwitter.com", "fixvx.com",
+    "nitter.*",
+];
+
+// The ID of the tweet is the first group.
+static STATUS_PATH: &str = r"^/(?:[^/]+/status(?:es)?|i/web/status|i/status)/(\d+)";
+
+/// The URLs handled by the Twitter client. These are the status URLs
+/// on Twitter and its various mirrors.
+pub fn urlRules() -> Result<Vec<UrlRule>, Error>
+{
+    TWITTER_HOSTS.iter().map(|host| UrlRule::withPath(host, STATUS_PATH))
+        .collect()
+}
+
+/// Get the ID of the tweet at `url`.
+pub fn tweetId(url: &str) -> Result<String, Error>
+{
+    let u = Url::parse(url).map_err(|_| rterr!("Invalid URL: {}", url))?;
+    if !urlRules()?.iter().any(|rule| rule.matches(&u))
+    {
+        return Err(rterr!("Not a tweet URL: {}", url));
+    }
+    let re = regex::Regex::new(STATUS_PATH).unwrap();
+    re.captures(u.path()).and_then(|c| c.get(1))
+        .map(|id| id.as_str().to_owned())
+        .ok_or_else(|| rterr!("Not a tweet URL: {}", url))
 }
 
-fn getTweetMedia(media_data: &serde_json::Value) ->
-    Result<Option<TempItem>, Error>
+/// Get the media resource in `media_data`, which is an item in the
+/// “extended_entities.media” of a tweet. The resource is a URL, with
+/// the alt text, and the length and dimensions of videos in its info.
+pub fn getTweetMedia(media_data: &serde_json::Value) ->
+    Result<Option<Resource>, Error>
 {
+    let mut info = ResourceInfo {
+        description: media_data["ext_alt_text"].as_str()
+            .filter(|s| !s.is_empty()).map(|s| s.to_owned()),
+        ..ResourceInfo::default()
+    };
     match media_data["type"].as_str()
         .ok_or_else(|| rterr!("Failed to get tweet media type"))?
     {
-        "video" =>
+        "video" | "animated_gif" =>
         {
-            let variants = media_data["video_info"]["variants"].as_array()
+            let video_info = &media_data["video_info"];
+            let variants = video_info["variants"].as_array()
                 .ok_or_else(|| rterr!("Failed to get tweet video variants"))?;
-            let info = variants.iter()
+            let variant = variants.iter()
                 .max_by_key(|v| v["bitrate"].as_i64().or(Some(0)).unwrap())
                 .ok_or_else(|| rterr!("Empty tweet video variants"))?;
-            let url = info["url"].as_str().ok_or_else(
+            let url = variant["url"].as_str().ok_or_else(
                 || rterr!("Tweet video variant does not have URL"))?;
-            Ok(Some(TempItem::Url(url.to_owned())))
+            info.content_type = variant["content_type"].as_str()
+                .map(|s| s.to_owned());
+            info.duration_ms = video_info["duration_millis"].as_u64();
+            if let (Some(w), Some(h)) = (video_info["aspect_ratio"][0].as_u64(),
+                                         video_info["aspect_ratio"][1].as_u64())
+            {
+                info.aspect_ratio = Some(format!("{}:{}", w, h));
+            }
+            Ok(Some(Resource { item: TempItem::Url(url.to_owned()), info }))
         },
         "photo" =>
         {
             let url = media_data["media_url"].as_str().ok_or_else(
                 || rterr!("Tweet photo does not have URL"))?;
-            Ok(Some(TempItem::Url(url.to_owned())))
+            Ok(Some(Resource { item: TempItem::Url(url.to_owned()), info }))
         },
         _ => Ok(None),
     }
 }
 
-impl analyser::ResourceAnalyser for Client
+/// Get the full text of the tweet in `data`, with the t.co links
+/// replaced by the URLs they point to.
+pub fn tweetText(data: &serde_json::Value) -> Result<String, Error>
+{
+    let mut text = data["full_text"].as_str().or_else(|| data["text"].as_str())
+        .ok_or_else(|| rterr!("Failed to get tweet text"))?.to_owned();
+    let entities = data["entities"]["urls"].as_array().into_iter().flatten()
+        .chain(data["entities"]["media"].as_array().into_iter().flatten());
+    for entity in entities
+    {
+        if let (Some(short), Some(expanded)) =
+            (entity["url"].as_str(), entity["expanded_url"].as_str())
+        {
+            text = text.replace(short, expanded);
+        }
+    }
+    Ok(text)
+}
+
+/// Escape `text` for HTML, and turn the URLs in it into links.
+fn linkify(text: &str) -> String
+{
+    let re = regex::Regex::new(r"https?://[^\s<]+").unwrap();
+    let escaped = html::escape(text);
+    re.replace_all(&escaped, r#"<a href="$0">$0</a>"#)
+        .replace('\n', "<br>\n")
+}
+
+/// Render the tweet in `data` into a piece of HTML. Media are
+/// referenced by their original URLs, which are replaced with the
+/// local files when the record is created.
+fn renderTweet(data: &serde_json::Value) -> Result<String, Error>
 {
-    fn analyse(&self, url: &str) -> Result<Vec<TempItem>, Error>
-    {
-        let mut resources = Vec::new();
-        let u = Url::parse(url).map_err(|_| rterr!("Invalid URL: {}", url))?;
-        let id = u.path_segments()
-            .ok_or_else(|| rterr!("Invalid Tweet URL: {}", url))?
-            .last().ok_or_else(|| rterr!("Invalid Tweet URL: {}", url))?;
-        let data = self.getTweet(id)?;
-        resources.push(
-            TempItem::Text(data["text"].as_str()
-                           .ok_or_else(|| rterr!("Failed to get tweet text"))?
-                           .to_owned()));
-        if let Some(medias) = data["extended_entities"]["media"].as_array()
+    let user = &data["user"];
+    let name = user["name"].as_str().unwrap_or("");
+    let handle = user["screen_name"].as_str().unwrap_or("");
+    let retweeted = &data["retweeted_status"];
+    if retweeted.is_object()
+    {
+        return Ok(format!("<div class=\"meta\">{} retweeted</div>\n{}",
+                          html::escape(name), renderTweet(retweeted)?));
+    }
+
+    let mut result = String::from("<div class=\"tweet\">\n<div class=\"author\">");
+    if let Some(avatar) = user["profile_image_url_https"].as_str()
+    {
+        result.push_str(&format!("<img class=\"avatar\" src=\"{}\"> ",
+                                 html::escape(avatar)));
+    }
+    result.push_str(&format!(
+        "<b>{}</b> <span class=\"meta\">@{}</span></div>\n<p>{}</p>\n",
+        html::escape(name), html::escape(handle), linkify(&tweetText(data)?)));
+    if let Some(medias) = data["extended_entities"]["media"].as_array()
+    {
+        for media in medias
         {
-            for media in medias
+            if let Some(Resource { item: TempItem::Url(u), info }) =
+                getTweetMedia(media)?
             {
-                if let Some(stuff) = getTweetMedia(media)?
+                if media["type"].as_str() == Some("photo")
                 {
-                    if let TempItem::Url(u) = &stuff
-                    {
-                        info!("Found Twitter media at {}.", u);
-                    }
-                    resources.push(stuff);
+                    let alt = info.description.map(
+                        |d| format!(" alt=\"{}\"", html::escape(&d)))
+                        .unwrap_or_default();
+                    result.push_str(&format!("<p><img src=\"{}\"{}></p>\n",
+                                             html::escape(&u), alt));
+                }
+                else
+                {
+                    result.push_str(&format!(
+                        "<p><video controls src=\"{}\"></video></p>\n",
+                        html::escape(&u)));
                 }
             }
         }
-        Ok(resources)
+    }
+    let quoted = &data["quoted_status"];
+    if quoted.is_object()
+    {
+        result.push_str(&format!("<blockquote>\n{}</blockquote>\n",
+                                 renderTweet(quoted)?));
+    }
+    // The author could be unknown for tweets from an export.
+    let author_path = if handle.is_empty() { "i/web" } else { handle };
+    result.push_str(&format!(
+        "<div class=\"meta\"><a href=\"https://twitter.com/{}/status/{}\">{}</a></div>\n</div>\n",
+        html::escape(author_path), data["id_str"].as_str().unwrap_or(""),
+        html::escape(data["created_at"].as_str().unwrap_or(""))));
+    Ok(result)
+}
+
+/// Render `tweets` into a standalone HTML page.
+pub fn renderPage(tweets: &[serde_json::Value]) -> Result<String, Error>
+{
+    let title = format!("{} on Twitter",
+                        tweets[0]["user"]["name"].as_str().unwrap_or(""));
+    let mut body = String::new();
+    for tweet in tweets
+    {
+        body.push_str(&renderTweet(tweet)?);
+    }
+    Ok(html::page(&title, &body))
+}
+
+/// Make a record title from the text of a tweet.
+pub fn tweetTitle(text: &str) -> String
+{
+    let line = text.lines().next().unwrap_or("");
+    let mut title: String = line.chars().take(60).collect();
+    if title.chars().count() < text.chars().count()
+    {
+        title.push('…');
+    }
+    title
+}
+
+/// The URL of the tweet in `data`.
+pub fn tweetUrl(data: &serde_json::Value) -> Result<String, Error>
+{
+    let id = data["id_str"].as_str()
+        .ok_or_else(|| rterr!("Tweet does not have an ID"))?;
+    let author = data["user"]["screen_name"].as_str().unwrap_or("i/web");
+    Ok(format!("https://twitter.com/{}/status/{}", author, id))
+}
+
+/// Collect the avatar URLs of the authors of the tweet in `data` and
+/// the tweets it references into `avatars`, without duplication.
+fn collectAvatars(data: &serde_json::Value, avatars: &mut Vec<String>)
+{
+    if let Some(u) = data["user"]["profile_image_url_https"].as_str()
+    {
+        if !avatars.iter().any(|a| a == u)
+        {
+            avatars.push(u.to_owned());
+        }
+    }
+    for key in ["retweeted_status", "quoted_status"]
+    {
+        if data[key].is_object()
+        {
+            collectAvatars(&data[key], avatars);
+        }
+    }
+}
+
+/// Add the text and media of the tweet in `data` to `analysis`.
+/// `relation` is how this tweet relates to the tweet being archived.
+fn addTweet(data: &serde_json::Value, relation: Option<&str>,
+            analysis: &mut Analysis) -> Result<(), Error>
+{
+    let info = ResourceInfo {
+        relation: relation.map(|r| r.to_owned()),
+        ..ResourceInfo::default()
+    };
+    analysis.addWithInfo(TempItem::Text(tweetText(data)?), info.clone());
+    if let Some(medias) = data["extended_entities"]["media"].as_array()
+    {
+        for media in medias
+        {
+            if let Some(stuff) = getTweetMedia(media)?
+            {
+                if let TempItem::Url(u) = &stuff.item
+                {
+                    info!("Found Twitter media at {}.", u);
+                }
+                analysis.addWithInfo(stuff.item, ResourceInfo {
+                    relation: info.relation.clone(),
+                    ..stuff.info
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Add the tweet in `data` to `analysis`, along with the tweets it
+/// retweets or quotes. Without those the text of the tweet is
+/// usually meaningless. The raw API response is also saved, in case
+/// we need it later.
+fn addTweetWithReferenced(data: &serde_json::Value, analysis: &mut Analysis)
+                          -> Result<(), Error>
+{
+    addTweet(data, None, analysis)?;
+    let retweeted = &data["retweeted_status"];
+    if retweeted.is_object()
+    {
+        info!("Found retweeted tweet {}.",
+              retweeted["id_str"].as_str().unwrap_or(""));
+        addTweet(retweeted, Some("retweeted"), analysis)?;
+    }
+    // A retweet of a quote tweet has the quoted tweet in both the
+    // retweet and the retweeted tweet.
+    let quoted = if retweeted["quoted_status"].is_object()
+    {
+        &retweeted["quoted_status"]
+    }
+    else
+    {
+        &data["quoted_status"]
+    };
+    if quoted.is_object()
+    {
+        info!("Found quoted tweet {}.", quoted["id_str"].as_str().unwrap_or(""));
+        addTweet(quoted, Some("quoted"), analysis)?;
+    }
+    let raw = serde_json::to_string_pretty(data)
+        .map_err(|_| rterr!("Failed to serialize tweet"))?;
+    analysis.addWithRole(TempItem::Json(raw), "raw");
+    Ok(())
+}
+
+/// Make the resources of a record from `tweets`, which are usually
+/// one tweet, or a thread.
+pub fn analyseTweets(tweets: &[serde_json::Value]) -> Result<Analysis, Error>
+{
+    let mut analysis = Analysis::new();
+    let mut avatars = Vec::new();
+    for tweet in tweets
+    {
+        addTweetWithReferenced(tweet, &mut analysis)?;
+        collectAvatars(tweet, &mut avatars);
+    }
+    for avatar in avatars
+    {
+        analysis.addWithRole(TempItem::Url(avatar), "avatar");
+    }
+    analysis.addWithRole(TempItem::Html(renderPage(tweets)?), "page");
+    Ok(analysis)
+}
+
+impl analyser::ResourceAnalyser for Client
+{
+    fn analyse(&self, url: &str, _scratch: &Path) -> Result<Analysis, Error>
+    {
+        let id = tweetId(url)?;
+        let data = self.getTweet(&id)?;
+        let tweets = if self.thread
+        {
+            let thread = self.getThread(data)?;
+            info!("Found {} tweets in thread.", thread.len());
+            thread
+        }
+        else
+        {
+            vec![data]
+        };
+        analyseTweets(&tweets)
     }
 }
 
@@ -318,15 +1024,169 @@ mod tests
     #[test]
     fn analyse() -> Result<(), Error>
     {
+        let scratch = std::env::temp_dir();
         let client = Client::new(GuestToken::new()?)?;
-        let items = client.analyse("https://twitter.com/MetroWind/status/1595694065353248768")?;
-        assert_eq!(items.len(), 2);
-        assert_eq!(items[0], TempItem::Text("刚刚剁手了这些…… https://t.co/WRoKBpQXyb".to_owned()));
+        let analysis = client.analyse("https://twitter.com/MetroWind/status/1595694065353248768", &scratch)?;
+        let items = analysis.items();
+        assert_eq!(items.len(), 5);
+        assert_eq!(items[0], &TempItem::Text("刚刚剁手了这些…… https://twitter.com/MetroWind/status/1595694065353248768/photo/1".to_owned()));
         match items[1]
         {
             TempItem::Url(_) => assert!(true),
             _ => assert!(false),
         }
+        assert!(matches!(items[2], TempItem::Json(_)));
+        assert!(matches!(items[4], TempItem::Html(_)));
+        Ok(())
+    }
+
+    #[test]
+    fn urls() -> Result<(), Error>
+    {
+        for url in ["https://twitter.com/MetroWind/status/1595694065353248768",
+                    "https://x.com/MetroWind/status/1595694065353248768?s=20",
+                    "https://mobile.twitter.com/MetroWind/status/1595694065353248768/photo/1",
+                    "https://fxtwitter.com/MetroWind/status/1595694065353248768",
+                    "https://vxtwitter.com/MetroWind/statuses/1595694065353248768",
+                    "https://nitter.net/MetroWind/status/1595694065353248768#m",
+                    "https://twitter.com/i/web/status/1595694065353248768"]
+        {
+            assert_eq!(tweetId(url)?, "1595694065353248768");
+        }
+        assert!(tweetId("https://twitter.com/MetroWind").is_err());
+        assert!(tweetId("https://twitter.com/MetroWind/likes").is_err());
+        assert!(tweetId("https://example.org/a/status/1595694065353248768").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn threadReplies() -> Result<(), Error>
+    {
+        let replies: Vec<serde_json::Value> = serde_json::from_str(r#"[
+            {"id_str": "4", "in_reply_to_status_id_str": "3", "user": {"id_str": "100"}},
+            {"id_str": "3", "in_reply_to_status_id_str": "2", "user": {"id_str": "100"}},
+            {"id_str": "5", "in_reply_to_status_id_str": "4", "user": {"id_str": "200"}},
+            {"id_str": "2", "in_reply_to_status_id_str": "1", "user": {"id_str": "100"}}
+        ]"#).unwrap();
+        let chain = chainReplies("1", "100", &replies);
+        let ids: Vec<&str> = chain.iter()
+            .map(|t| t["id_str"].as_str().unwrap()).collect();
+        assert_eq!(ids, vec!["2", "3", "4"]);
+        Ok(())
+    }
+
+    #[test]
+    fn referencedTweets() -> Result<(), Error>
+    {
+        let data: serde_json::Value = serde_json::from_str(r#"{
+            "text": "RT @b: look at this",
+            "retweeted_status": {
+                "id_str": "2", "text": "look at this",
+                "quoted_status": {"id_str": "3", "text": "Something"}},
+            "quoted_status": {"id_str": "3", "text": "Something"}}"#).unwrap();
+        let mut analysis = Analysis::new();
+        addTweetWithReferenced(&data, &mut analysis)?;
+        let relations: Vec<Option<&str>> = analysis.resources.iter()
+            .map(|r| r.info.relation.as_deref()).collect();
+        assert_eq!(relations, vec![None, Some("retweeted"), Some("quoted"), None]);
+        assert_eq!(analysis.resources[2].item,
+                   TempItem::Text(String::from("Something")));
+        Ok(())
+    }
+
+    #[test]
+    fn fullText() -> Result<(), Error>
+    {
+        let data: serde_json::Value = serde_json::from_str(r#"{
+            "full_text": "Read https://t.co/abc and see https://t.co/xyz",
+            "text": "Read https://t.co/abc…",
+            "entities": {
+                "urls": [{"url": "https://t.co/abc",
+                          "expanded_url": "https://example.org/article"}],
+                "media": [{"url": "https://t.co/xyz",
+                           "expanded_url": "https://twitter.com/a/status/1/photo/1"}]}}"#)
+            .unwrap();
+        assert_eq!(tweetText(&data)?, "Read https://example.org/article and see https://twitter.com/a/status/1/photo/1");
+        Ok(())
+    }
+
+    #[test]
+    fn render() -> Result<(), Error>
+    {
+        let data: serde_json::Value = serde_json::from_str(r#"{
+            "id_str": "1", "created_at": "Wed Nov 23 06:00:00 +0000 2022",
+            "full_text": "A <b> https://t.co/abc",
+            "user": {"name": "Some One", "screen_name": "someone",
+                     "profile_image_url_https": "https://pbs.twimg.com/a.jpg"},
+            "entities": {"urls": [{"url": "https://t.co/abc",
+                                   "expanded_url": "https://example.org/"}]},
+            "extended_entities": {"media": [
+                {"type": "photo", "media_url": "http://pbs.twimg.com/b.jpg"}]},
+            "quoted_status": {"id_str": "2", "full_text": "Quoted",
+                              "user": {"name": "Other", "screen_name": "other"}}
+        }"#).unwrap();
+        let page = renderPage(std::slice::from_ref(&data))?;
+        assert!(page.contains("<title>Some One on Twitter</title>"));
+        assert!(page.contains(r#"<img class="avatar" src="https://pbs.twimg.com/a.jpg">"#));
+        assert!(page.contains(r#"A &lt;b&gt; <a href="https://example.org/">https://example.org/</a>"#));
+        assert!(page.contains(r#"<img src="http://pbs.twimg.com/b.jpg">"#));
+        assert!(page.contains("<blockquote>"));
+        assert!(page.contains("https://twitter.com/other/status/2"));
+        let mut avatars = Vec::new();
+        collectAvatars(&data, &mut avatars);
+        assert_eq!(avatars, vec![String::from("https://pbs.twimg.com/a.jpg")]);
+        Ok(())
+    }
+
+    #[test]
+    fn mediaInfo() -> Result<(), Error>
+    {
+        let video: serde_json::Value = serde_json::from_str(r#"{
+            "type": "video", "ext_alt_text": "A cat",
+            "video_info": {
+                "aspect_ratio": [16, 9], "duration_millis": 12345,
+                "variants": [
+                    {"content_type": "application/x-mpegURL",
+                     "url": "https://video.twimg.com/a.m3u8"},
+                    {"bitrate": 832000, "content_type": "video/mp4",
+                     "url": "https://video.twimg.com/a.mp4"}]}}"#).unwrap();
+        let media = getTweetMedia(&video)?.unwrap();
+        assert_eq!(media.item, TempItem::Url(String::from("https://video.twimg.com/a.mp4")));
+        assert_eq!(media.info.description.as_deref(), Some("A cat"));
+        assert_eq!(media.info.content_type.as_deref(), Some("video/mp4"));
+        assert_eq!(media.info.duration_ms, Some(12345));
+        assert_eq!(media.info.aspect_ratio.as_deref(), Some("16:9"));
+
+        let photo: serde_json::Value = serde_json::from_str(r#"{
+            "type": "photo", "ext_alt_text": null,
+            "media_url": "http://pbs.twimg.com/media/a.jpg"}"#).unwrap();
+        let media = getTweetMedia(&photo)?.unwrap();
+        assert_eq!(media.info, ResourceInfo::default());
+        Ok(())
+    }
+
+    #[test]
+    fn title()
+    {
+        assert_eq!(tweetTitle("Short"), "Short");
+        assert_eq!(tweetTitle("First line\nSecond line"), "First line…");
+    }
+
+    #[test]
+    fn rateLimit() -> Result<(), Error>
+    {
+        let res: ureq::Response = "HTTP/1.1 429 Too Many Requests\r\n\
+                                   x-rate-limit-remaining: 0\r\n\
+                                   x-rate-limit-reset: 1000\r\n\r\n".parse()
+            .map_err(|_| rterr!("Invalid response"))?;
+        let limit = RateLimit::fromResponse(&res).unwrap();
+        assert_eq!(limit, RateLimit { remaining: 0, reset: 1000 });
+        assert_eq!(limit.wait(990), Duration::from_secs(11));
+        assert_eq!(limit.wait(1000), Duration::ZERO);
+        assert_eq!(limit.wait(0), MAX_RATE_LIMIT_WAIT);
+        assert_eq!(RateLimit { remaining: 5, reset: 1000 }.wait(990), Duration::ZERO);
+        assert_eq!(endpointOf("https://api.twitter.com/1.1/statuses/show.json?id=1"),
+                   "https://api.twitter.com/1.1/statuses/show.json");
         Ok(())
     }
 }
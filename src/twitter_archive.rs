@@ -0,0 +1,335 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+use regex::Regex;
+use serde_json::Value;
+
+use crate::analyser::{Analysis, Resource, ResourceInfo, TempItem};
+use crate::config::Config;
+use crate::error::Error;
+use crate::organizer;
+use crate::organizer::createRecord;
+use crate::twitter;
+
+/// An official Twitter data export, either as the downloaded zip
+/// file, or extracted into a directory.
+enum Source
+{
+    Dir(PathBuf),
+    Zip(zip::ZipArchive<File>),
+}
+
+fn listDir(root: &Path, dir: &Path, names: &mut Vec<String>) -> Result<(), Error>
+{
+    for entry in dir.read_dir().map_err(
+        |_| rterr!("Failed to access directory at {:?}", dir))?
+    {
+        let path = entry.map_err(
+            |e| rterr!("Failed to access directory at {:?}: {}", dir, e))?.path();
+        if path.is_dir()
+        {
+            listDir(root, &path, names)?;
+        }
+        else if let Some(name) = path.strip_prefix(root).ok()
+            .and_then(|p| p.to_str())
+        {
+            names.push(name.replace('\\', "/"));
+        }
+    }
+    Ok(())
+}
+
+impl Source
+{
+    fn open(path: &Path) -> Result<Self, Error>
+    {
+        if path.is_dir()
+        {
+            return Ok(Self::Dir(path.to_owned()));
+        }
+        let f = File::open(path).map_err(
+            |e| rterr!("Failed to open {:?}: {}", path, e))?;
+        let archive = zip::ZipArchive::new(f).map_err(
+            |e| rterr!("Failed to open zip file {:?}: {}", path, e))?;
+        Ok(Self::Zip(archive))
+    }
+
+    /// Relative paths of all the files in the export, separated by
+    /// “/”.
+    fn names(&self) -> Result<Vec<String>, Error>
+    {
+        match self
+        {
+            Self::Dir(root) =>
+            {
+                let mut names = Vec::new();
+                listDir(root, root, &mut names)?;
+                Ok(names)
+            },
+            Self::Zip(archive) =>
+                Ok(archive.file_names().map(|n| n.to_owned()).collect()),
+        }
+    }
+
+    fn read(&mut self, name: &str) -> Result<Vec<u8>, Error>
+    {
+        let mut data = Vec::new();
+        match self
+        {
+            Self::Dir(root) =>
+            {
+                data = std::fs::read(root.join(name)).map_err(
+                    |e| rterr!("Failed to read {} in export: {}", name, e))?;
+            },
+            Self::Zip(archive) =>
+            {
+                archive.by_name(name).map_err(
+                    |e| rterr!("Failed to find {} in export: {}", name, e))?
+                    .read_to_end(&mut data).map_err(
+                        |e| rterr!("Failed to read {} in export: {}", name, e))?;
+            },
+        }
+        Ok(data)
+    }
+}
+
+/// Parse a data file in the export. These are JavaScript files like
+///
+/// window.YTD.tweets.part0 = [ ... ]
+fn parseDataFile(content: &[u8]) -> Result<Vec<Value>, Error>
+{
+    let content = std::str::from_utf8(content)
+        .map_err(|_| rterr!("Invalid data file in export"))?;
+    let json = &content[content.find('=').ok_or_else(
+        || rterr!("Invalid data file in export"))? + 1..];
+    serde_json::from_str(json)
+        .map_err(|e| rterr!("Invalid data file in export: {}", e))
+}
+
+fn readDataFiles(source: &mut Source, names: &[String], pattern: &str) ->
+    Result<Vec<Value>, Error>
+{
+    let re = Regex::new(pattern).unwrap();
+    let mut result = Vec::new();
+    for name in names.iter().filter(|n| re.is_match(n))
+    {
+        info!("Reading {}...", name);
+        result.append(&mut parseDataFile(&source.read(name)?)?);
+    }
+    Ok(result)
+}
+
+/// Find the file of the media at `url` of tweet `id` in the media
+/// directory of the export. Media files are named as
+/// “<tweet ID>-<filename in URL>”.
+fn findMedia<'a>(names: &'a [String], id: &str, url: &str) -> Option<&'a str>
+{
+    let filename = url.split('?').next()?.rsplit('/').next()?;
+    let is_media = |n: &&String| {
+        n.starts_with("data/tweets_media/") || n.starts_with("data/tweet_media/")
+    };
+    let target = format!("{}-{}", id, filename);
+    if let Some(name) = names.iter().filter(is_media)
+        .find(|n| n.ends_with(&format!("/{}", target)))
+    {
+        return Some(name);
+    }
+    // The export may have a different variant of a video than the
+    // one with the highest bitrate.
+    if filename.ends_with(".mp4")
+    {
+        let prefix = format!("{}-", id);
+        return names.iter().filter(is_media)
+            .find(|n| n.rsplit('/').next().unwrap().starts_with(&prefix) &&
+                  n.ends_with(".mp4"))
+            .map(|n| n.as_str());
+    }
+    None
+}
+
+/// Build the resources of a tweet from the export, without
+/// downloading anything.
+fn analyseTweet(tweet: &Value, source: &mut Source, names: &[String],
+                scratch: &Path) -> Result<Analysis, Error>
+{
+    let id = tweet["id_str"].as_str()
+        .ok_or_else(|| rterr!("Tweet in export does not have an ID"))?;
+    let mut analysis = Analysis::new();
+    analysis.info.published = tweet["created_at"].as_str().map(|s| s.to_owned());
+    analysis.addWithInfo(TempItem::Text(twitter::tweetText(tweet)?),
+                         ResourceInfo::default());
+    for media in tweet["extended_entities"]["media"].as_array().into_iter().flatten()
+    {
+        let (url, media_info) = match twitter::getTweetMedia(media)?
+        {
+            Some(Resource { item: TempItem::Url(u), info }) => (u, info),
+            _ => continue,
+        };
+        let name = match findMedia(names, id, &url)
+        {
+            Some(n) => n.to_owned(),
+            None =>
+            {
+                warn!("Media {} of tweet {} is not in the export.", url, id);
+                continue;
+            },
+        };
+        // The record takes the file away, so make a copy.
+        let target = scratch.join(name.rsplit('/').next().unwrap());
+        std::fs::write(&target, source.read(&name)?).map_err(
+            |e| rterr!("Failed to write {:?}: {}", target, e))?;
+        analysis.addWithInfo(TempItem::File(target), ResourceInfo {
+            url: Some(url),
+            ..media_info
+        });
+    }
+    let raw = serde_json::to_string_pretty(tweet)
+        .map_err(|_| rterr!("Failed to serialize tweet"))?;
+    analysis.addWithRole(TempItem::Json(raw), "raw");
+    analysis.addWithRole(TempItem::Html(
+        twitter::renderPage(std::slice::from_ref(tweet))?), "page");
+    Ok(analysis)
+}
+
+/// Import the tweets (or likes if `likes` is true) from the Twitter
+/// export at `path` into `category`, one record per tweet. Tweets
+/// that are already archived are skipped. Return the number of
+/// records created.
+pub fn import(path: &Path, category: &str, likes: bool, conf: &Config) ->
+    Result<usize, Error>
+{
+    let mut source = Source::open(path)?;
+    let names = source.names()?;
+
+    // The export does not have the author in each tweet, which is
+    // needed for rendering.
+    let account = readDataFiles(&mut source, &names, r"^data/account\.js$")?;
+    let account = &account.first().map(|a| a["account"].clone())
+        .unwrap_or_default();
+    let user = serde_json::json!({
+        "name": account["accountDisplayName"],
+        "screen_name": account["username"],
+    });
+
+    let tweets: Vec<Value> = if likes
+    {
+        readDataFiles(&mut source, &names, r"^data/like(-part\d+)?\.js$")?
+            // Likes of deleted or protected tweets have no text.
+            .into_iter().map(|like| serde_json::json!({
+                "id_str": like["like"]["tweetId"],
+                "full_text": like["like"]["fullText"].as_str().unwrap_or(""),
+            })).collect()
+    }
+    else
+    {
+        readDataFiles(&mut source, &names, r"^data/tweets?(-part\d+)?\.js$")?
+            .into_iter().map(|t| {
+                let mut tweet = t["tweet"].clone();
+                tweet["user"] = user.clone();
+                tweet
+            }).collect()
+    };
+    info!("Found {} tweets in export.", tweets.len());
+
+    let mut count = 0;
+    for tweet in &tweets
+    {
+        let id = match tweet["id_str"].as_str()
+        {
+            Some(id) => id,
+            None => continue,
+        };
+        let dir = conf.root_dir.join(category).join(id);
+        if dir.join(organizer::METADATA_FILE).exists()
+        {
+            continue;
+        }
+        let scratch = tempfile::Builder::new().prefix("cain-").tempdir()
+            .map_err(|e| rterr!("Failed to create scratch directory: {}", e))?;
+        // One broken tweet should not stop the import.
+        let analysis = match analyseTweet(tweet, &mut source, &names, scratch.path())
+        {
+            Ok(a) => a,
+            Err(e) =>
+            {
+                warn!("Skipping tweet {}: {}", id, e);
+                continue;
+            },
+        };
+        let text = twitter::tweetText(tweet).unwrap_or_default();
+        let title = if text.trim().is_empty()
+        {
+            format!("Tweet {}", id)
+        }
+        else
+        {
+            twitter::tweetTitle(&text)
+        };
+        std::fs::create_dir_all(&dir).map_err(
+            |_| rterr!("Failed to create directory at {:?}", dir))?;
+        createRecord(analysis, &title, &twitter::tweetUrl(tweet)?, &dir)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn importDir() -> Result<()>
+    {
+        let export = tempfile::tempdir()?;
+        let data_dir = export.path().join("data");
+        std::fs::create_dir_all(data_dir.join("tweets_media"))?;
+        std::fs::write(data_dir.join("account.js"), r#"window.YTD.account.part0 = [
+            {"account": {"username": "someone", "accountDisplayName": "Some One"}}]"#)?;
+        std::fs::write(data_dir.join("tweets.js"), r#"window.YTD.tweets.part0 = [
+            {"tweet": {"id_str": "1", "full_text": "Hello",
+                       "created_at": "Wed Nov 23 06:00:00 +0000 2022",
+                       "extended_entities": {"media": [
+                           {"type": "photo",
+                            "media_url": "http://pbs.twimg.com/media/abc.jpg"}]}}},
+            {"tweet": {"id_str": "2", "full_text": "World"}}]"#)?;
+        std::fs::write(data_dir.join("tweets_media").join("1-abc.jpg"), "image")?;
+
+        let root = tempfile::tempdir()?;
+        let conf = Config { root_dir: root.path().to_owned(),
+                            ..Config::default() };
+        assert_eq!(import(export.path(), "tweets", false, &conf)?, 2);
+        // Importing again does nothing.
+        assert_eq!(import(export.path(), "tweets", false, &conf)?, 0);
+
+        let record = root.path().join("tweets").join("1");
+        let image = format!("{}.jpg", organizer::hashData(b"image"));
+        assert!(record.join(&image).exists());
+        let metadata = std::fs::read_to_string(record.join(organizer::METADATA_FILE))?;
+        assert!(metadata.contains("https://twitter.com/someone/status/1"));
+        assert!(metadata.contains("http://pbs.twimg.com/media/abc.jpg"));
+        // The rendered page points to the local image.
+        let page = record.read_dir()?.filter_map(|e| e.ok())
+            .find(|e| e.path().extension().and_then(|x| x.to_str()) == Some("html"))
+            .unwrap();
+        let page = std::fs::read_to_string(page.path())?;
+        assert!(page.contains(&format!("<img src=\"{}\">", image)));
+        // The original export is intact.
+        assert!(data_dir.join("tweets_media").join("1-abc.jpg").exists());
+
+        // Likes of deleted tweets have no text, and tweets without
+        // an ID are skipped.
+        std::fs::write(data_dir.join("like.js"), r#"window.YTD.like.part0 = [
+            {"like": {"tweetId": "3", "fullText": "Liked"}},
+            {"like": {"tweetId": "4"}},
+            {"like": {"fullText": "No ID"}}]"#)?;
+        assert_eq!(import(export.path(), "likes", true, &conf)?, 2);
+        let metadata = std::fs::read_to_string(
+            root.path().join("likes").join("4").join(organizer::METADATA_FILE))?;
+        assert!(metadata.contains("Tweet 4"));
+        Ok(())
+    }
+}
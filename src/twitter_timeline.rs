@@ -0,0 +1,252 @@
+use log::info;
+use serde_json::Value;
+
+use crate::config::{Config, TwitterAuth};
+use crate::error::Error;
+use crate::organizer;
+use crate::organizer::createRecord;
+use crate::runtime_config;
+use crate::twitter;
+
+/// What to archive from a Twitter account.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Timeline
+{
+    Tweets,
+    Likes,
+    Bookmarks,
+}
+
+impl Timeline
+{
+    fn name(&self) -> &'static str
+    {
+        match self
+        {
+            Self::Tweets => "tweets",
+            Self::Likes => "likes",
+            Self::Bookmarks => "bookmarks",
+        }
+    }
+}
+
+/// The key in the runtime config of the newest archived tweet in the
+/// timeline. For likes and bookmarks, it is the most recently liked or
+/// bookmarked one.
+fn highWaterKey(timeline: Timeline, handle: &str) -> String
+{
+    if handle.is_empty()
+    {
+        format!("twitter_{}_newest", timeline.name())
+    }
+    else
+    {
+        format!("twitter_{}_{}_newest", timeline.name(), handle.to_lowercase())
+    }
+}
+
+fn numericId(tweet: &Value) -> Option<u64>
+{
+    tweet["id_str"].as_str()?.parse().ok()
+}
+
+/// Page backward through a timeline with `getPage`, which takes the
+/// max ID of a page, until it runs out. Return the tweets, newest
+/// first.
+fn pageThrough<F>(mut getPage: F) -> Result<Vec<Value>, Error>
+    where F: FnMut(Option<u64>) -> Result<Vec<Value>, Error>
+{
+    let mut tweets = Vec::new();
+    let mut max_id = None;
+    loop
+    {
+        let page = getPage(max_id)?;
+        match page.iter().filter_map(numericId).min()
+        {
+            Some(oldest) if oldest > 0 && max_id != Some(oldest - 1) =>
+                max_id = Some(oldest - 1),
+            _ => break,
+        }
+        tweets.extend(page);
+        info!("Got {} tweets so far...", tweets.len());
+    }
+    Ok(tweets)
+}
+
+/// Take the IDs in a page until `newest` is found. Return
+/// whether it is found.
+fn takeNewIds(page: Vec<String>, newest: Option<&str>, ids: &mut Vec<String>) ->
+    bool
+{
+    for id in page
+    {
+        if Some(id.as_str()) == newest
+        {
+            return true;
+        }
+        ids.push(id);
+    }
+    false
+}
+
+/// Likes and bookmarks are listed in the order they were made, not by
+/// tweet ID, so `since_id` and `max_id` do not work for them. Page
+/// through them with `getPage`, which takes a page token, until
+/// `newest`, the newest one archived by the last run, is seen. Return
+/// the IDs, newest first.
+fn newIds<F>(mut getPage: F, newest: Option<&str>) -> Result<Vec<String>, Error>
+    where F: FnMut(Option<&str>) -> Result<(Vec<String>, Option<String>), Error>
+{
+    let mut ids = Vec::new();
+    let mut page_token: Option<String> = None;
+    loop
+    {
+        let (page, next) = getPage(page_token.as_deref())?;
+        if takeNewIds(page, newest, &mut ids)
+        {
+            break;
+        }
+        match next
+        {
+            Some(t) => page_token = Some(t),
+            None => break,
+        }
+        info!("Got {} tweets so far...", ids.len());
+    }
+    Ok(ids)
+}
+
+/// Get the tweets with `ids`, in the same order.
+fn lookupInOrder(client: &twitter::Client, ids: &[String]) -> Result<Vec<Value>, Error>
+{
+    let mut tweets = Vec::new();
+    for chunk in ids.chunks(100)
+    {
+        tweets.append(&mut client.lookupTweets(chunk)?);
+    }
+    tweets.sort_by_key(|t| ids.iter().position(
+        |id| Some(id.as_str()) == t["id_str"].as_str()));
+    Ok(tweets)
+}
+
+/// Archive the tweets in `timeline` of `handle` into `category`, one
+/// record per tweet. The handle is not used for bookmarks, which are
+/// always of the authenticated user. Only tweets newer than the ones
+/// archived by the last run are archived. Return the number of
+/// records created.
+pub fn archive(timeline: Timeline, handle: &str, category: &str, conf: &Config)
+               -> Result<usize, Error>
+{
+    if timeline != Timeline::Tweets &&
+        !matches!(conf.twitter_auth, TwitterAuth::StaticToken { .. })
+    {
+        return Err(rterr!("Archiving {} needs StaticToken authentication",
+                          timeline.name()));
+    }
+    let client = twitter::Client::fromConfig(conf)?;
+    let handle = if timeline == Timeline::Bookmarks {""} else {handle};
+    let key = highWaterKey(timeline, handle);
+    let newest = runtime_config::get(&key)?;
+
+    let mut tweets = match timeline
+    {
+        Timeline::Tweets => pageThrough(
+            |max_id| client.getUserTweets(handle, newest.as_deref(), max_id))?,
+        Timeline::Likes =>
+        {
+            let user_id = client.getUserId(handle)?;
+            let ids = newIds(|t| client.getLikedIds(&user_id, t), newest.as_deref())?;
+            lookupInOrder(&client, &ids)?
+        },
+        Timeline::Bookmarks =>
+        {
+            let token = conf.twitter_config.bookmarks_token.clone().ok_or_else(
+                || rterr!("Archiving bookmarks needs an OAuth 2.0 user token. \
+                           Set bookmarks_token in twitter_config."))?;
+            let user_client = twitter::Client::new(
+                twitter::UserToken { access_token: token })?;
+            let user_id = user_client.getSelfId()?;
+            let ids = newIds(|t| user_client.getBookmarkIds(&user_id, t),
+                             newest.as_deref())?;
+            lookupInOrder(&client, &ids)?
+        },
+    };
+    info!("Found {} new tweets.", tweets.len());
+
+    // Archive the oldest first, so that the high-water mark can be
+    // kept if this is interrupted.
+    tweets.reverse();
+    let mut count = 0;
+    for tweet in &tweets
+    {
+        let id = match tweet["id_str"].as_str()
+        {
+            Some(id) => id,
+            None => continue,
+        };
+        let dir = conf.root_dir.join(category).join(id);
+        if !dir.join(organizer::METADATA_FILE).exists()
+        {
+            info!("Archiving tweet {}...", id);
+            let analysis = twitter::analyseTweets(std::slice::from_ref(tweet))?;
+            std::fs::create_dir_all(&dir).map_err(
+                |_| rterr!("Failed to create directory at {:?}", dir))?;
+            createRecord(analysis, &twitter::tweetTitle(&twitter::tweetText(tweet)?),
+                         &twitter::tweetUrl(tweet)?, &dir)?;
+            count += 1;
+        }
+        runtime_config::set(&key, id)?;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn paging() -> Result<()>
+    {
+        let all: Vec<Value> = (1..=5).rev()
+            .map(|i| serde_json::json!({"id_str": i.to_string()})).collect();
+        let mut max_ids = Vec::new();
+        let tweets = pageThrough(|max_id| {
+            max_ids.push(max_id);
+            Ok(all.iter().filter(|t| max_id.is_none_or(|m| numericId(t).unwrap() <= m))
+               .take(2).cloned().collect())
+        })?;
+        assert_eq!(tweets, all);
+        assert_eq!(max_ids, vec![None, Some(3), Some(1), Some(0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn bookmarks() -> Result<()>
+    {
+        let mut ids = Vec::new();
+        assert!(!takeNewIds(vec![String::from("9"), String::from("3")], Some("5"),
+                            &mut ids));
+        assert!(takeNewIds(vec![String::from("7"), String::from("5"),
+                                String::from("1")], Some("5"), &mut ids));
+        assert_eq!(ids, vec!["9", "3", "7"]);
+
+        // Likes of older tweets are newer than likes of newer tweets.
+        let pages = [(vec!["2", "8"], Some("a")), (vec!["1", "6"], Some("b")),
+                         (vec!["9"], None)];
+        let mut tokens = Vec::new();
+        let ids = newIds(|t| {
+            tokens.push(t.map(|s| s.to_owned()));
+            let (page, next) = &pages[tokens.len() - 1];
+            Ok((page.iter().map(|s| s.to_string()).collect(), next.map(|s| s.to_owned())))
+        }, Some("6"))?;
+        assert_eq!(ids, vec!["2", "8", "1"]);
+        assert_eq!(tokens, vec![None, Some(String::from("a"))]);
+        assert_eq!(newIds(|_| Ok((vec![String::from("3")], None)), None)?, vec!["3"]);
+        assert_eq!(highWaterKey(Timeline::Likes, "MetroWind"),
+                   "twitter_likes_metrowind_newest");
+        assert_eq!(highWaterKey(Timeline::Bookmarks, ""), "twitter_bookmarks_newest");
+        Ok(())
+    }
+}
@@ -0,0 +1,168 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use log::info;
+
+use crate::analyser;
+use crate::analyser::{Analysis, RecordInfo, TempItem, UrlRule};
+use crate::config::VideoConfig;
+use crate::error::Error;
+
+/// The URLs handled by the video downloader.
+pub fn urlRules() -> Result<Vec<UrlRule>, Error>
+{
+    Ok(vec![
+        UrlRule::withPath("youtube.com", r"^/(watch|shorts/|live/)")?,
+        UrlRule::withPath("*.youtube.com", r"^/(watch|shorts/|live/)")?,
+        UrlRule::new("youtu.be"),
+        UrlRule::withPath("vimeo.com", r"^/\d+")?,
+        UrlRule::withPath("www.bilibili.com", r"^/video/")?,
+    ])
+}
+
+/// Figure out what a file downloaded by yt-dlp is from its name.
+/// Return `None` if the file should not be in the record.
+fn roleOf(path: &Path) -> Option<&'static str>
+{
+    let name = path.file_name()?.to_str()?;
+    if name.ends_with(".info.json")
+    {
+        return Some("info");
+    }
+    match path.extension()?.to_str()?
+    {
+        "vtt" | "srt" | "ass" | "ssa" | "ttml" | "lrc" | "json3" |
+        "srv1" | "srv2" | "srv3" => Some("subtitles"),
+        "jpg" | "jpeg" | "png" | "webp" => Some("thumbnail"),
+        // Leftovers of an incomplete download.
+        "part" | "ytdl" | "temp" => None,
+        _ => Some("video"),
+    }
+}
+
+/// Get the record info from the info JSON written by yt-dlp.
+fn parseInfo(info_file: &Path) -> Result<RecordInfo, Error>
+{
+    let content = std::fs::read_to_string(info_file).map_err(
+        |e| rterr!("Failed to read video info at {:?}: {}", info_file, e))?;
+    let data: serde_json::Value = serde_json::from_str(&content).map_err(
+        |_| rterr!("Invalid video info at {:?}", info_file))?;
+    let authors = data["uploader"].as_str()
+        .or_else(|| data["channel"].as_str())
+        .map(|s| vec![s.to_owned()]).unwrap_or_default();
+    // Upload date is in the form of YYYYMMDD.
+    let published = data["upload_date"].as_str().map(|d| {
+        if d.len() == 8 && d.is_ascii()
+        {
+            format!("{}-{}-{}", &d[..4], &d[4..6], &d[6..])
+        }
+        else
+        {
+            d.to_owned()
+        }
+    });
+    Ok(RecordInfo {
+        title: data["title"].as_str().map(|s| s.to_owned()),
+        authors,
+        published,
+    })
+}
+
+pub struct Downloader
+{
+    conf: VideoConfig,
+}
+
+impl Downloader
+{
+    pub fn new(conf: VideoConfig) -> Self
+    {
+        Self { conf }
+    }
+}
+
+impl analyser::ResourceAnalyser for Downloader
+{
+    fn analyse(&self, url: &str, scratch: &Path) -> Result<Analysis, Error>
+    {
+        let dir = scratch.join("yt-dlp");
+        std::fs::create_dir_all(&dir).map_err(
+            |e| rterr!("Failed to create directory at {:?}: {}", dir, e))?;
+        let output = dir.join("video.%(ext)s");
+
+        let mut proc = Command::new(&self.conf.program);
+        proc.args(["--no-playlist", "--write-info-json", "--write-thumbnail",
+                   "--write-subs"])
+            .args(["-f", &self.conf.format])
+            .args(["--sub-langs", &self.conf.subtitle_langs])
+            .arg("-o").arg(&output);
+        if self.conf.auto_subtitles
+        {
+            proc.arg("--write-auto-subs");
+        }
+        proc.arg(url);
+        let status = proc.status().map_err(
+            |e| rterr!("Failed to run {}: {}", self.conf.program, e))?;
+        if !status.success()
+        {
+            return Err(rterr!("{} failed with code {}", self.conf.program,
+                              status.code().unwrap_or(0)));
+        }
+
+        let mut files: Vec<(&'static str, PathBuf)> = dir.read_dir().map_err(
+            |_| rterr!("Failed to access directory at {:?}", dir))?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter_map(|path| roleOf(&path).map(|role| (role, path)))
+            .collect();
+        // Put the video first.
+        files.sort_by_key(|(role, path)| (*role != "video", *role, path.clone()));
+
+        let mut analysis = Analysis::new();
+        for (role, path) in files
+        {
+            if role == "info"
+            {
+                analysis.info = parseInfo(&path)?;
+            }
+            info!("Found {} at {:?}.", role, path);
+            analysis.addWithRole(TempItem::File(path), role);
+        }
+        if !analysis.resources.iter().any(|r| r.info.role.as_deref() == Some("video"))
+        {
+            return Err(rterr!("{} did not download any video", self.conf.program));
+        }
+        Ok(analysis)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn fileRoles()
+    {
+        assert_eq!(roleOf(Path::new("/a/video.mp4")), Some("video"));
+        assert_eq!(roleOf(Path::new("/a/video.en.vtt")), Some("subtitles"));
+        assert_eq!(roleOf(Path::new("/a/video.webp")), Some("thumbnail"));
+        assert_eq!(roleOf(Path::new("/a/video.info.json")), Some("info"));
+        assert_eq!(roleOf(Path::new("/a/video.f137.mp4.part")), None);
+    }
+
+    #[test]
+    fn info() -> Result<()>
+    {
+        let dir = tempfile::tempdir()?;
+        let info_file = dir.path().join("video.info.json");
+        std::fs::write(&info_file, r#"{"title": "A video", "uploader": "Someone",
+                                       "upload_date": "20221123"}"#)?;
+        let info = parseInfo(&info_file)?;
+        assert_eq!(info.title.as_deref(), Some("A video"));
+        assert_eq!(info.authors, vec![String::from("Someone")]);
+        assert_eq!(info.published.as_deref(), Some("2022-11-23"));
+        Ok(())
+    }
+}
@@ -0,0 +1,261 @@
+//! Writing the HTTP traffic of an archiving run as a WARC file
+//! (ISO 28500, WARC 1.1). Every record is compressed as a separate
+//! gzip member, as is usual for `.warc.gz` files.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use sha1::{Digest, Sha1};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use url::Url;
+
+use crate::error::Error;
+
+static BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+fn base32(data: &[u8]) -> String
+{
+    let mut result = String::new();
+    for chunk in data.chunks(5)
+    {
+        let mut buffer = [0u8; 5];
+        buffer[..chunk.len()].copy_from_slice(chunk);
+        let bits = buffer.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64);
+        let chars = (chunk.len() * 8).div_ceil(5);
+        for i in 0..8
+        {
+            if i < chars
+            {
+                let index = (bits >> (35 - i * 5)) & 0x1f;
+                result.push(BASE32_ALPHABET[index as usize] as char);
+            }
+            else
+            {
+                result.push('=');
+            }
+        }
+    }
+    result
+}
+
+/// The digest of `data` in the form used by the WARC digest fields.
+fn digest(data: &[u8]) -> String
+{
+    format!("sha1:{}", base32(&Sha1::digest(data)))
+}
+
+/// A random (version 4) UUID as a WARC record ID.
+fn recordId() -> String
+{
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("<urn:uuid:{}-{}-{}-{}-{}>", &hex[..8], &hex[8..12], &hex[12..16],
+            &hex[16..20], &hex[20..])
+}
+
+fn now() -> String
+{
+    let t = OffsetDateTime::now_utc();
+    t.replace_nanosecond(0).unwrap_or(t).format(&Rfc3339).unwrap_or_default()
+}
+
+/// The head of an HTTP GET request of `url` with `headers`.
+pub fn requestHead(url: &Url, headers: &[(&str, &str)]) -> Vec<u8>
+{
+    let mut target = url.path().to_owned();
+    if let Some(q) = url.query()
+    {
+        target.push('?');
+        target.push_str(q);
+    }
+    let mut host = url.host_str().unwrap_or("").to_owned();
+    if let Some(port) = url.port()
+    {
+        host.push_str(&format!(":{}", port));
+    }
+    let mut head = format!("GET {} HTTP/1.1\r\nHost: {}\r\n", target, host);
+    for (name, value) in headers
+    {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    head.into_bytes()
+}
+
+/// The head of the HTTP response `res`, without the Content-Length
+/// header. The body is recorded as it was decoded by the client, so
+/// the transfer and content encodings are removed from the headers,
+/// and the length is added by [`Writer::writeExchange`] when it is
+/// known.
+pub fn responseHead(res: &ureq::Response) -> String
+{
+    let mut head = format!("{} {} {}\r\n", res.http_version(), res.status(),
+                           res.status_text());
+    for name in res.headers_names()
+    {
+        let lower = name.to_lowercase();
+        if lower == "transfer-encoding" || lower == "content-encoding" ||
+            lower == "content-length"
+        {
+            continue;
+        }
+        for value in res.all(&name)
+        {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+    }
+    head
+}
+
+pub struct Writer
+{
+    out: BufWriter<File>,
+}
+
+impl Writer
+{
+    /// Create a WARC file at `path`, starting with a warcinfo record.
+    pub fn create(path: &Path) -> Result<Self, Error>
+    {
+        let file = File::create(path).map_err(
+            |e| rterr!("Failed to create WARC file at {:?}: {}", path, e))?;
+        let mut writer = Self { out: BufWriter::new(file) };
+        let filename = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        let info = format!(
+            "software: cain/{}\r\nformat: WARC File Format 1.1\r\n\
+             conformsTo: http://iipc.github.io/warc-specifications/specifications/warc-format/warc-1.1/\r\n",
+            env!("CARGO_PKG_VERSION"));
+        writer.writeRecord(&[
+            ("WARC-Type", String::from("warcinfo")),
+            ("WARC-Record-ID", recordId()),
+            ("WARC-Date", now()),
+            ("WARC-Filename", filename.to_owned()),
+            ("Content-Type", String::from("application/warc-fields")),
+        ], info.as_bytes())?;
+        Ok(writer)
+    }
+
+    fn writeRecord(&mut self, headers: &[(&str, String)], block: &[u8]) ->
+        Result<(), Error>
+    {
+        let mut record = b"WARC/1.1\r\n".to_vec();
+        for (name, value) in headers
+        {
+            record.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+        }
+        record.extend_from_slice(format!("Content-Length: {}\r\n\r\n", block.len())
+                                 .as_bytes());
+        record.extend_from_slice(block);
+        record.extend_from_slice(b"\r\n\r\n");
+
+        let mut encoder = GzEncoder::new(&mut self.out, Compression::default());
+        encoder.write_all(&record).and_then(|_| encoder.finish().map(|_| ()))
+            .map_err(|e| rterr!("Failed to write WARC record: {}", e))
+    }
+
+    /// Record a request of `url` and its response. `request` is the
+    /// head of the request, `response` is the head of the response
+    /// from [`responseHead`], and `body` is the body of the response.
+    /// If the body was cut off because it was too long, `truncated`
+    /// should be true, and the record is marked as such.
+    pub fn writeExchange(&mut self, url: &Url, request: &[u8], response: &str,
+                         body: &[u8], truncated: bool) -> Result<(), Error>
+    {
+        let date = now();
+        let response_id = recordId();
+        // The length of a truncated body is unknown.
+        let mut block = if truncated
+        {
+            format!("{}\r\n", response)
+        }
+        else
+        {
+            format!("{}Content-Length: {}\r\n\r\n", response, body.len())
+        }.into_bytes();
+        block.extend_from_slice(body);
+        let mut headers = vec![
+            ("WARC-Type", String::from("response")),
+            ("WARC-Record-ID", response_id.clone()),
+            ("WARC-Date", date.clone()),
+            ("WARC-Target-URI", url.to_string()),
+            ("Content-Type", String::from("application/http;msgtype=response")),
+            ("WARC-Block-Digest", digest(&block)),
+            ("WARC-Payload-Digest", digest(body)),
+        ];
+        if truncated
+        {
+            headers.push(("WARC-Truncated", String::from("length")));
+        }
+        self.writeRecord(&headers, &block)?;
+        self.writeRecord(&[
+            ("WARC-Type", String::from("request")),
+            ("WARC-Record-ID", recordId()),
+            ("WARC-Date", date),
+            ("WARC-Target-URI", url.to_string()),
+            ("WARC-Concurrent-To", response_id),
+            ("Content-Type", String::from("application/http;msgtype=request")),
+            ("WARC-Block-Digest", digest(request)),
+        ], request)
+    }
+
+    pub fn finish(mut self) -> Result<(), Error>
+    {
+        self.out.flush().map_err(|e| rterr!("Failed to write WARC file: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::io::Read;
+    use anyhow::Result;
+
+    #[test]
+    fn encoding()
+    {
+        assert_eq!(base32(b"foobar"), "MZXW6YTBOI======");
+        assert_eq!(digest(b""), "sha1:3I42H3S6NNFQ2MSVX7XZKYAYSCX5QBYJ");
+        let id = recordId();
+        assert_eq!(id.len(), 47);
+        assert_eq!(&id[24..25], "4");
+    }
+
+    #[test]
+    fn records() -> Result<()>
+    {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("test.warc.gz");
+        let url = Url::parse("https://example.com:8080/a?b=c")?;
+        let mut writer = Writer::create(&path)?;
+        let request = requestHead(&url, &[("Accept", "*/*")]);
+        assert_eq!(String::from_utf8(request.clone())?,
+                   "GET /a?b=c HTTP/1.1\r\nHost: example.com:8080\r\nAccept: */*\r\n\r\n");
+        writer.writeExchange(&url, &request, "HTTP/1.1 200 OK\r\n", b"hello", false)?;
+        writer.writeExchange(&url, &request, "HTTP/1.1 200 OK\r\n", b"hel", true)?;
+        writer.finish()?;
+
+        let mut content = String::new();
+        flate2::read::MultiGzDecoder::new(File::open(&path)?)
+            .read_to_string(&mut content)?;
+        let records: Vec<&str> = content.split("WARC/1.1\r\n").skip(1).collect();
+        assert_eq!(records.len(), 5);
+        assert!(records[0].contains("WARC-Type: warcinfo\r\n"));
+        assert!(records[0].contains("WARC-Filename: test.warc.gz\r\n"));
+        assert!(records[1].contains("WARC-Type: response\r\n"));
+        assert!(records[1].contains("WARC-Target-URI: https://example.com:8080/a?b=c\r\n"));
+        assert!(records[1].ends_with(
+            "\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello\r\n\r\n"));
+        assert!(!records[1].contains("WARC-Truncated"));
+        assert!(records[2].contains("WARC-Type: request\r\n"));
+        assert!(records[3].contains("WARC-Truncated: length\r\n"));
+        assert!(records[3].ends_with("\r\n\r\nHTTP/1.1 200 OK\r\n\r\nhel\r\n\r\n"));
+        Ok(())
+    }
+}
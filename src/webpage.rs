@@ -1,37 +1,136 @@
-use std::path::PathBuf;
+use std::path::Path;
 use std::process::Command;
 
+use log::{info, warn};
+use url::Url;
+
 use crate::analyser;
-use crate::analyser::TempItem;
+use crate::analyser::{Analysis, RecordInfo, ResourceInfo, TempItem};
+use crate::article;
+use crate::chromium;
+use crate::config::{ChromiumConfig, SinglePageConfig, WebPageBackend};
 use crate::error::Error;
+use crate::inliner::Inliner;
+use crate::warc;
 
 pub struct Downloader
 {
     download_font: bool,
     disable_js: bool,
+    backend: WebPageBackend,
+    warc: bool,
+    extract_article: bool,
+    chromium: ChromiumConfig,
 }
 
 impl Downloader
 {
-    pub fn new(download_font: bool, disable_js: bool) -> Self
+    pub fn new(conf: SinglePageConfig) -> Self
     {
-        Self { download_font, disable_js }
+        Self { download_font: conf.download_font, disable_js: conf.disable_js,
+               backend: conf.backend, warc: conf.warc,
+               extract_article: conf.extract_article, chromium: conf.chromium }
     }
-}
 
-impl analyser::ResourceAnalyser for Downloader
-{
-    fn analyse(&self, url: &str) -> Result<Vec<TempItem>, Error>
+    fn runNative(&self, url: &str, scratch: &Path) -> Result<Analysis, Error>
+    {
+        info!("Saving {} with the built-in inliner...", url);
+        let mut inliner = Inliner::new(self.download_font, self.disable_js);
+        let warc_file = scratch.join("page.warc.gz");
+        if self.warc
+        {
+            inliner.recordWarc(warc::Writer::create(&warc_file)?);
+        }
+        let page = inliner.inlinePage(url);
+        // Finish the WARC file even if the page failed, so that it is
+        // not left half written.
+        let has_warc = inliner.finishWarc()?;
+        let mut analysis = Analysis::from(vec![TempItem::Html(page?)]);
+        if has_warc
+        {
+            analysis.addWithInfo(TempItem::File(warc_file), ResourceInfo {
+                role: Some(String::from("warc")),
+                content_type: Some(String::from("application/warc")),
+                ..ResourceInfo::default()
+            });
+        }
+        Ok(analysis)
+    }
+
+    /// Extract the article from the page saved in `analysis`, and add
+    /// it to `analysis`.
+    fn addArticle(&self, analysis: &mut Analysis, scratch: &Path) -> Result<(), Error>
     {
-        // Create a temp file
-        let mut temp_file = PathBuf::from(std::env::temp_dir());
-        temp_file.push("cain-monolith.html");
+        let page = match analysis.resources.first().map(|r| &r.item)
+        {
+            Some(TempItem::Html(page)) => page.clone(),
+            Some(TempItem::File(path)) => std::fs::read(path).map(
+                |data| String::from_utf8_lossy(&data).into_owned()).map_err(
+                |e| rterr!("Failed to read {:?}: {}", path, e))?,
+            _ => return Err(rterr!("Web page not found in the analysis")),
+        };
+        let article = match article::extract(&page)
+        {
+            Some(article) => article,
+            None =>
+            {
+                info!("No article is found in the page.");
+                return Ok(());
+            },
+        };
+        info!("Found article “{}”.", article.title.as_deref().unwrap_or(""));
+        let markdown_file = scratch.join("article.md");
+        std::fs::write(&markdown_file, article::renderMarkdown(&article)).map_err(
+            |e| rterr!("Failed to write {:?}: {}", markdown_file, e))?;
+        analysis.addWithRole(TempItem::Html(article::renderHtml(&article)), "article");
+        analysis.addWithInfo(TempItem::File(markdown_file), ResourceInfo {
+            role: Some(String::from("article_markdown")),
+            content_type: Some(String::from("text/markdown")),
+            ..ResourceInfo::default()
+        });
+        analysis.info = RecordInfo {
+            title: article.title,
+            authors: article.authors,
+            published: article.published,
+        };
+        Ok(())
+    }
 
-        // Download the URL to the temp file
+    /// Render the page in Chromium, and save the resulting DOM with
+    /// the built-in inliner. The scripts are removed, because they
+    /// have already run.
+    fn runChromium(&self, url: &str, scratch: &Path) -> Result<Analysis, Error>
+    {
+        let rendering = chromium::render(url, &self.chromium, scratch)?;
+        let base = Url::parse(&rendering.url).map_err(
+            |_| rterr!("Invalid URL from Chromium: {}", rendering.url))?;
+        let inliner = Inliner::new(self.download_font, true);
+        let mut analysis = Analysis::from(vec![TempItem::Html(
+            inliner.inlineHtml(&rendering.html, &base)?)]);
+        let files = [(rendering.pdf, "page.pdf", "print", "application/pdf"),
+                     (rendering.screenshot, "screenshot.png", "screenshot", "image/png")];
+        for (data, name, role, content_type) in files
+        {
+            if let Some(data) = data
+            {
+                let path = scratch.join(name);
+                std::fs::write(&path, data).map_err(
+                    |e| rterr!("Failed to write {:?}: {}", path, e))?;
+                analysis.addWithInfo(TempItem::File(path), ResourceInfo {
+                    role: Some(String::from(role)),
+                    content_type: Some(String::from(content_type)),
+                    ..ResourceInfo::default()
+                });
+            }
+        }
+        Ok(analysis)
+    }
+
+    fn runMonolith(&self, url: &str, scratch: &Path) -> Result<Analysis, Error>
+    {
+        let output = scratch.join("page.html");
         let mut proc = Command::new("monolith");
-        proc.args(&["--no-audio", "--isolate", "-o",
-                    temp_file.to_str().ok_or_else(
-                        || rterr!("Empty output file for Monolith"))?]);
+        proc.args(["--no-audio", "--isolate", "-o"]).arg(&output);
         if !self.download_font
         {
             proc.arg("--no-fonts");
@@ -42,11 +141,19 @@ impl analyser::ResourceAnalyser for Downloader
         }
 
         proc.arg(url);
-        let status = proc.status().map_err(
-            |e| rterr!("Failed to run Monolith: {}", e))?;
+        let status = match proc.status()
+        {
+            Ok(status) => status,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound =>
+            {
+                warn!("Monolith is not found. Using the built-in inliner instead.");
+                return self.runNative(url, scratch);
+            },
+            Err(e) => return Err(rterr!("Failed to run Monolith: {}", e)),
+        };
         if status.success()
         {
-            Ok(vec![TempItem::File(temp_file)])
+            Ok(Analysis::from(vec![TempItem::File(output)]))
         }
         else
         {
@@ -56,6 +163,46 @@ impl analyser::ResourceAnalyser for Downloader
     }
 }
 
+impl analyser::ResourceAnalyser for Downloader
+{
+    fn analyse(&self, url: &str, scratch: &Path) -> Result<Analysis, Error>
+    {
+        // Every run gets its own directory, so that runs sharing a
+        // scratch directory (e.g. for a discussion and its article)
+        // never overwrite each other’s files. It is removed along
+        // with the scratch directory, whether the record is created
+        // or not.
+        let scratch = tempfile::Builder::new().prefix("webpage-").tempdir_in(scratch)
+            .map_err(|e| rterr!("Failed to create scratch directory: {}", e))?.keep();
+        let scratch = scratch.as_path();
+        // Only the built-in inliner can record the HTTP traffic, so
+        // it replaces Monolith. Chromium is never replaced, because it
+        // is chosen for pages that need JavaScript.
+        let mut analysis = match self.backend
+        {
+            WebPageBackend::Chromium =>
+            {
+                if self.warc
+                {
+                    warn!("Pages rendered in Chromium cannot be saved as WARC.");
+                }
+                self.runChromium(url, scratch)?
+            },
+            _ if self.warc => self.runNative(url, scratch)?,
+            WebPageBackend::Monolith => self.runMonolith(url, scratch)?,
+            WebPageBackend::Native => self.runNative(url, scratch)?,
+        };
+        if self.extract_article
+        {
+            if let Err(e) = self.addArticle(&mut analysis, scratch)
+            {
+                warn!("Failed to extract the article: {}", e);
+            }
+        }
+        Ok(analysis)
+    }
+}
+
 #[cfg(test)]
 mod tests
 {
@@ -65,8 +212,15 @@ mod tests
     #[test]
     fn analyse() -> Result<(), Error>
     {
-        let downloader = Downloader::new(false, true);
-        let items = downloader.analyse("http://example.org/")?;
+        let scratch = tempfile::tempdir().map_err(
+            |e| rterr!("Failed to create scratch directory: {}", e))?;
+        let downloader = Downloader::new(SinglePageConfig {
+            download_font: false, disable_js: true,
+            backend: WebPageBackend::Monolith, warc: false,
+            extract_article: false, chromium: ChromiumConfig::default(),
+        });
+        let analysis = downloader.analyse("http://example.org/", scratch.path())?;
+        let items = analysis.items();
         assert_eq!(items.len(), 1);
         match items[0]
         {